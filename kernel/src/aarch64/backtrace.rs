@@ -13,6 +13,7 @@
 
 use crate::{
     backtrace::BacktraceItem,
+    exception::arch_exception::ExceptionContext,
     memory::{Address, Virtual},
 };
 use aarch64_cpu::registers::*;
@@ -88,7 +89,13 @@ impl<'a> Iterator for StackFrameRecordIterator<'a> {
 }
 
 fn stack_frame_record_iterator<'a>() -> Option<StackFrameRecordIterator<'a>> {
-    let fp = Address::<Virtual>::new(FP.get() as usize);
+    stack_frame_record_iterator_from(Address::new(FP.get() as usize))
+}
+
+/// Start iterating at the frame record `fp` points to.
+fn stack_frame_record_iterator_from<'a>(
+    fp: Address<Virtual>,
+) -> Option<StackFrameRecordIterator<'a>> {
     if !fp.is_valid_stack_addr() {
         return None;
     }
@@ -106,3 +113,18 @@ fn stack_frame_record_iterator<'a>() -> Option<StackFrameRecordIterator<'a>> {
 pub fn backtrace(f: impl FnOnce(Option<&mut dyn Iterator<Item = BacktraceItem>>)) {
     f(stack_frame_record_iterator().as_mut().map(|s| s as _))
 }
+
+/// Architectural implementation of the backtrace of an interrupted context.
+///
+/// Follows the frame pointer the context was interrupted with, so the function it was interrupted
+/// in is not part of the iteration.
+pub fn backtrace_of(
+    e: &ExceptionContext,
+    f: impl FnOnce(Option<&mut dyn Iterator<Item = BacktraceItem>>),
+) {
+    let fp = Address::new(e.gpr[29] as usize);
+
+    f(stack_frame_record_iterator_from(fp)
+        .as_mut()
+        .map(|s| s as _))
+}
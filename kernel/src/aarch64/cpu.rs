@@ -19,6 +19,12 @@ use aarch64_cpu::asm;
 
 pub use asm::nop;
 
+/// Sleep until an interrupt is pending.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    asm::wfi()
+}
+
 /// Pause execution on the core.
 #[inline(always)]
 pub fn wait_forever() -> ! {
@@ -26,3 +32,19 @@ pub fn wait_forever() -> ! {
         asm::wfe()
     }
 }
+
+/// Continue at `entry` with the stack pointer set to `sp`, abandoning the current stack.
+///
+/// # Safety
+///
+/// - `sp` must point to the end of a stack that nothing else uses.
+#[inline(always)]
+pub unsafe fn jump_with_stack(entry: unsafe fn() -> !, sp: u64) -> ! {
+    core::arch::asm!(
+        "mov sp, {sp}",
+        "br {entry}",
+        sp = in(reg) sp,
+        entry = in(reg) entry,
+        options(noreturn)
+    )
+}
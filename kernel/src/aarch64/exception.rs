@@ -13,7 +13,7 @@
 
 use crate::{
     cpu::{self, core_id},
-    exception, info, memory, symbols,
+    exception, info, memory, smp, symbols, thread,
 };
 use aarch64_cpu::{asm::barrier, registers::*};
 use core::{arch::global_asm, cell::UnsafeCell, fmt};
@@ -58,13 +58,59 @@ pub struct ExceptionContext {
     pub esr_el1: u64,
 
     pub sp_el0: u64,
-    pub _res_sp: u64,
+
+    /// Thread pointer. Points to the thread-local storage of the interrupted thread.
+    pub tpidr_el1: u64,
 }
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
 
+/// Panics naming the thread whose stack overflowed, if the exception is a data abort on the guard
+/// page right below the running thread's stack.
+///
+/// Aborts elsewhere, e.g. on the stack of a thread that already exited, are left to the default
+/// handler. The abort is taken on SP_EL1, so there is stack left to report it.
+fn check_stack_overflow(exc: &ExceptionContext) {
+    let esr = EsrEL1(InMemoryRegister::new(exc.esr_el1));
+    if esr.exception_class() != Some(ESR_EL1::EC::Value::DataAbortCurrentEL) {
+        return;
+    }
+
+    let Some(tls) = thread::tls::current() else {
+        return;
+    };
+
+    let far = memory::Address::new(FAR_EL1.get() as usize);
+    if !memory::stack_alloc::is_guard_page_addr(tls.stack_bottom(), far) {
+        return;
+    }
+
+    panic!(
+        "Stack overflow in thread PID={} NAME={} on Core{}: guard page hit at {}\n\n{}",
+        tls.pid(),
+        tls.name(),
+        core_id::<usize>(),
+        far,
+        exc
+    );
+}
+
+/// Lets the running thread use FP/SIMD if the exception was caused by its first FP/SIMD
+/// instruction. Returns true if so, in which case the instruction is executed again on return.
+///
+/// Without the `fp` feature, FP/SIMD use ends up in the default handler.
+fn handle_fp_trap(exc: &ExceptionContext) -> bool {
+    let esr = EsrEL1(InMemoryRegister::new(exc.esr_el1));
+    if !cfg!(feature = "fp") || esr.exception_class() != Some(ESR_EL1::EC::Value::TrappedFP) {
+        return false;
+    }
+
+    thread::enable_fp();
+    true
+}
+
 /// Prints verbose information about the exception and then panics.
 fn default_exception_handler(exc: &ExceptionContext) {
     let core: usize = core_id();
@@ -81,6 +127,11 @@ fn default_exception_handler(exc: &ExceptionContext) {
 
 #[no_mangle]
 extern "C" fn current_el0_synchronous(e: &mut ExceptionContext) {
+    if handle_fp_trap(e) {
+        return;
+    }
+
+    check_stack_overflow(e);
     default_exception_handler(e);
 }
 
@@ -88,6 +139,7 @@ extern "C" fn current_el0_synchronous(e: &mut ExceptionContext) {
 extern "C" fn current_el0_irq(e: &mut ExceptionContext) {
     let token = unsafe { &exception::asynchronous::IRQContext::new() };
     exception::asynchronous::irq_manager().handle_pending_irqs(token, e);
+    smp::park_if_requested();
 }
 
 #[no_mangle]
@@ -101,6 +153,7 @@ extern "C" fn current_el0_serror(_e: &mut ExceptionContext) {
 
 #[no_mangle]
 extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
+    check_stack_overflow(e);
     default_exception_handler(e);
 }
 
@@ -108,6 +161,7 @@ extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
 extern "C" fn current_elx_irq(e: &mut ExceptionContext) {
     let token = unsafe { &exception::asynchronous::IRQContext::new() };
     exception::asynchronous::irq_manager().handle_pending_irqs(token, e);
+    smp::park_if_requested();
 }
 
 #[no_mangle]
@@ -256,6 +310,7 @@ impl fmt::Display for ExceptionContext {
 
         writeln!(f, "FAR_EL1: {:#018x}", FAR_EL1.get() as usize)?;
         writeln!(f, "SP_EL0: {:#x}", self.sp_el0)?;
+        writeln!(f, "TPIDR_EL1: {:#x}", self.tpidr_el1)?;
 
         writeln!(f, "SPSR_EL1: {:#x}", self.spsr_el1)?;
         writeln!(f, "ELR_EL1: {:#018x}", self.elr_el1)?;
@@ -39,8 +39,10 @@ __vector_\handler:
 	stp	lr,  x1,  [sp, #16 * 15]
 	stp	x2,  x3,  [sp, #16 * 16]
 
-	mrs x1, SP_EL0
-	stp x1, x1, [sp, #16 * 17]
+	// Add the stack pointer of the interrupted thread and its thread pointer. Done out of line,
+	// because the vector entry has no room left. LR is already saved at this point.
+	bl	__exception_save_thread_state
+
 	// Build a stack frame for backtracing.
 .if \is_lower_el == 1
 	// If we came from a lower EL, make it a root frame (by storing zero) so that the kernel
@@ -158,6 +160,19 @@ __exception_vector_start:
 	CALL_WITH_CONTEXT lower_aarch32_serror, 1, 0
 .org 0x800
 
+//------------------------------------------------------------------------------
+// fn __exception_save_thread_state()
+//------------------------------------------------------------------------------
+// Store SP_EL0 and TPIDR_EL1 into the exception context on the stack. Only clobbers x4 and x5.
+__exception_save_thread_state:
+	mrs	x4, SP_EL0
+	mrs	x5, TPIDR_EL1
+	stp	x4, x5, [sp, #16 * 17]
+	ret
+
+.size	__exception_save_thread_state, . - __exception_save_thread_state
+.type	__exception_save_thread_state, function
+
 //------------------------------------------------------------------------------
 // fn __exception_restore_context()
 //------------------------------------------------------------------------------
@@ -170,6 +185,7 @@ __exception_restore_context:
 
 	ldp x0, x1, [sp, #16 * 17]
 	msr SP_EL0, x0
+	msr TPIDR_EL1, x1
 
 	ldp	x0,  x1,  [sp, #16 * 0]
 	ldp	x2,  x3,  [sp, #16 * 1]
@@ -225,15 +241,17 @@ __switch_to:
 	mov	x10,  lr
 	stp	lr,  x10,  [x0, #16 * 15]
 
-	//Saving stack pointer in SP_EL0
+	//Saving stack pointer in SP_EL0, next to the thread pointer
 	mov x2, sp
-	stp x2, x2, [x0, #16 * 17] 
+	mrs x3, TPIDR_EL1
+	stp x2, x3, [x0, #16 * 17]
 
 	//Restore
 	ldp	lr,  x20, [x1, #16 * 15]
 	msr ELR_EL1, x20 
 
 	ldp x0, x2, [x1, #16 * 17]
+	msr TPIDR_EL1, x2
 
 	//This is needed in case the kernel itself (SPSel=1) wants to switch to a thread directly
 	msr SPSel, 0
@@ -0,0 +1,77 @@
+//! Architectural FP/SIMD register handling.
+//!
+//! # Orientation
+//!
+//! Since arch modules are imported into generic modules using the path attribute, the path of this
+//! file is:
+//!
+//! crate::thread::fp::arch_fp
+
+use aarch64_cpu::{asm::barrier, registers::*};
+use core::arch::global_asm;
+use tock_registers::interfaces::ReadWriteable;
+
+// Assembly counterpart to this file.
+global_asm!(include_str!("fp.s"));
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Save area for the FP/SIMD registers of a thread.
+#[repr(C, align(16))]
+pub struct FpState {
+    /// V0 to V31.
+    v: [u128; 32],
+    fpcr: u64,
+    fpsr: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+extern "C" {
+    fn __fp_save(state: *mut FpState);
+    fn __fp_restore(state: *const FpState);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl FpState {
+    /// Create an instance with all registers zeroed.
+    pub const fn new() -> Self {
+        Self {
+            v: [0; 32],
+            fpcr: 0,
+            fpsr: 0,
+        }
+    }
+
+    /// Store the FP/SIMD registers of the executing core.
+    ///
+    /// FP/SIMD access must be enabled.
+    pub fn save(&mut self) {
+        unsafe { __fp_save(self) }
+    }
+
+    /// Enable FP/SIMD access on the executing core and load the registers.
+    pub fn restore(&self) {
+        enable();
+        unsafe { __fp_restore(self) }
+    }
+}
+
+/// Let FP/SIMD instructions execute.
+pub fn enable() {
+    CPACR_EL1.modify(CPACR_EL1::FPEN::TrapNothing);
+    barrier::isb(barrier::SY);
+}
+
+/// Make FP/SIMD instructions trap.
+pub fn disable() {
+    CPACR_EL1.modify(CPACR_EL1::FPEN::TrapEl0El1);
+    barrier::isb(barrier::SY);
+}
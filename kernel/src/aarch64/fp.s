@@ -0,0 +1,70 @@
+// The kernel is built without FP/SIMD, so the instructions below have to be enabled explicitly.
+.arch_extension fp
+.arch_extension simd
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+.section .text
+
+//------------------------------------------------------------------------------
+// fn __fp_save(state: *mut FpState)
+//------------------------------------------------------------------------------
+__fp_save:
+	stp	q0,  q1,  [x0, #32 * 0]
+	stp	q2,  q3,  [x0, #32 * 1]
+	stp	q4,  q5,  [x0, #32 * 2]
+	stp	q6,  q7,  [x0, #32 * 3]
+	stp	q8,  q9,  [x0, #32 * 4]
+	stp	q10, q11, [x0, #32 * 5]
+	stp	q12, q13, [x0, #32 * 6]
+	stp	q14, q15, [x0, #32 * 7]
+	stp	q16, q17, [x0, #32 * 8]
+	stp	q18, q19, [x0, #32 * 9]
+	stp	q20, q21, [x0, #32 * 10]
+	stp	q22, q23, [x0, #32 * 11]
+	stp	q24, q25, [x0, #32 * 12]
+	stp	q26, q27, [x0, #32 * 13]
+	stp	q28, q29, [x0, #32 * 14]
+	stp	q30, q31, [x0, #32 * 15]
+
+	mrs	x1,  FPCR
+	mrs	x2,  FPSR
+	str	x1,  [x0, #32 * 16]
+	str	x2,  [x0, #32 * 16 + 8]
+	ret
+
+.size	__fp_save, . - __fp_save
+.type	__fp_save, function
+.global	__fp_save
+
+//------------------------------------------------------------------------------
+// fn __fp_restore(state: *const FpState)
+//------------------------------------------------------------------------------
+__fp_restore:
+	ldp	q0,  q1,  [x0, #32 * 0]
+	ldp	q2,  q3,  [x0, #32 * 1]
+	ldp	q4,  q5,  [x0, #32 * 2]
+	ldp	q6,  q7,  [x0, #32 * 3]
+	ldp	q8,  q9,  [x0, #32 * 4]
+	ldp	q10, q11, [x0, #32 * 5]
+	ldp	q12, q13, [x0, #32 * 6]
+	ldp	q14, q15, [x0, #32 * 7]
+	ldp	q16, q17, [x0, #32 * 8]
+	ldp	q18, q19, [x0, #32 * 9]
+	ldp	q20, q21, [x0, #32 * 10]
+	ldp	q22, q23, [x0, #32 * 11]
+	ldp	q24, q25, [x0, #32 * 12]
+	ldp	q26, q27, [x0, #32 * 13]
+	ldp	q28, q29, [x0, #32 * 14]
+	ldp	q30, q31, [x0, #32 * 15]
+
+	ldr	x1,  [x0, #32 * 16]
+	ldr	x2,  [x0, #32 * 16 + 8]
+	msr	FPCR, x1
+	msr	FPSR, x2
+	ret
+
+.size	__fp_restore, . - __fp_restore
+.type	__fp_restore, function
+.global	__fp_restore
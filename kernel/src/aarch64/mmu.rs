@@ -15,10 +15,13 @@
 
 use crate::{
     memory,
-    memory::{mmu::TranslationGranule, Address, Physical},
+    memory::{
+        mmu::{MemoryRegion, TranslationGranule},
+        Address, Physical, Virtual,
+    },
 };
 use aarch64_cpu::{asm::barrier, registers::*};
-use core::intrinsics::unlikely;
+use core::{arch::asm, intrinsics::unlikely};
 use tock_registers::interfaces::{ReadWriteable, Readable, Writeable};
 
 //--------------------------------------------------------------------------------------------------
@@ -155,4 +158,19 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
     fn is_enabled(&self) -> bool {
         SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
     }
+
+    fn invalidate_tlb(&self, virt_region: &MemoryRegion<Virtual>) {
+        // Make the table updates visible to the table walkers of all cores first.
+        barrier::dsb(barrier::ISHST);
+
+        for virt_page_addr in virt_region.into_iter() {
+            // The operand holds VA[55:12], regardless of the granule size.
+            let operand = (virt_page_addr.into_inner().as_usize() >> 12) as u64;
+
+            unsafe { asm!("tlbi vaae1is, {}", in(reg) operand, options(nostack)) };
+        }
+
+        barrier::dsb(barrier::ISH);
+        barrier::isb(barrier::SY);
+    }
 }
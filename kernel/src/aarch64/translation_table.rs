@@ -445,6 +445,17 @@ impl<const NUM_TABLES: usize, const START_FROM_TOP: bool>
         Ok(())
     }
 
+    unsafe fn unmap_at(&mut self, virt_region: &MemoryRegion<Virtual>) -> Result<(), &'static str> {
+        assert!(self.initialized, "Translation tables not initialized");
+
+        for virt_page_addr in virt_region.into_iter() {
+            let (lvl2_index, lvl3_index) = self.lvl2_lvl3_index_from_page_addr(virt_page_addr)?;
+            self.lvl3[lvl2_index][lvl3_index] = PageDescriptor::new_zeroed();
+        }
+
+        Ok(())
+    }
+
     fn try_virt_page_addr_to_phys_page_addr(
         &self,
         virt_page_addr: PageAddress<Virtual>,
@@ -8,6 +8,7 @@
 mod arch_backtrace;
 
 use crate::{
+    exception::arch_exception::ExceptionContext,
     memory::{Address, Virtual},
     symbols,
 };
@@ -28,80 +29,113 @@ pub enum BacktraceItem {
 /// Pseudo-struct for printing a backtrace using its fmt::Display implementation.
 pub struct Backtrace;
 
+/// Pseudo-struct for printing the backtrace of an interrupted context using its fmt::Display
+/// implementation.
+///
+/// Starts at the instruction the context was interrupted at and follows its frame pointer.
+pub struct ContextBacktrace<'a>(pub &'a ExceptionContext);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+const SEPARATOR: &str =
+    "      ----------------------------------------------------------------------------------------------";
+
+fn write_header(f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "Backtrace:")?;
+    writeln!(f, "{}", SEPARATOR)?;
+    writeln!(
+        f,
+        "          Address            Function containing address"
+    )?;
+    writeln!(f, "{}", SEPARATOR)
+}
+
+fn write_link(f: &mut fmt::Formatter, number: usize, addr: Address<Virtual>) -> fmt::Result {
+    writeln!(
+        f,
+        "      {:>2}. {:016x} | {:<50}",
+        number,
+        addr.as_usize(),
+        match symbols::lookup_symbol(addr) {
+            Some(sym) => sym.name(),
+            _ => "Symbol not found",
+        }
+    )
+}
+
+/// Write the items of a backtrace, numbered from `first`.
+fn write_items(
+    f: &mut fmt::Formatter,
+    maybe_iter: Option<&mut dyn Iterator<Item = BacktraceItem>>,
+    first: usize,
+) -> fmt::Result {
+    let iter = match maybe_iter {
+        None => return writeln!(f, "ERROR! No valid stack frame found"),
+        Some(iter) => iter,
+    };
+
+    for (i, backtrace_res) in iter.enumerate() {
+        match backtrace_res {
+            BacktraceItem::InvalidFramePointer(addr) => {
+                writeln!(
+                    f,
+                    "      {:>2}. ERROR! \
+                    Encountered invalid frame pointer ({}) during backtrace",
+                    first + i,
+                    addr
+                )?;
+            }
+            BacktraceItem::InvalidLink(addr) => {
+                writeln!(
+                    f,
+                    "      {:>2}. ERROR! \
+                    Link address ({}) is not contained in kernel .text section",
+                    first + i,
+                    addr
+                )?;
+            }
+            BacktraceItem::Link(addr) => write_link(f, first + i, addr)?,
+        };
+    }
+
+    Ok(())
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
 impl fmt::Display for Backtrace {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "Backtrace:")?;
-        writeln!(
-            f,
-            "      ----------------------------------------------------------------------------------------------"
-        )?;
-        writeln!(
-            f,
-            "          Address            Function containing address"
-        )?;
-        writeln!(
-            f,
-            "      ----------------------------------------------------------------------------------------------"
-        )?;
+        write_header(f)?;
+
+        let mut fmt_res: fmt::Result = Ok(());
+        // Since the backtrace is printed, the first function is always core::fmt::write. Skip 1 so
+        // it is excluded and doesn't bloat the output.
+        arch_backtrace::backtrace(|maybe_iter| {
+            fmt_res = match maybe_iter {
+                Some(iter) => write_items(f, Some(&mut iter.skip(1)), 1),
+                None => write_items(f, None, 1),
+            }
+        });
+        fmt_res?;
+
+        writeln!(f, "{}", SEPARATOR)
+    }
+}
+
+impl fmt::Display for ContextBacktrace<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_header(f)?;
+
+        write_link(f, 1, Address::new(self.0.elr_el1 as usize))?;
 
         let mut fmt_res: fmt::Result = Ok(());
-        let trace_formatter =
-            |maybe_iter: Option<&mut dyn Iterator<Item = BacktraceItem>>| match maybe_iter {
-                None => fmt_res = writeln!(f, "ERROR! No valid stack frame found"),
-                Some(iter) => {
-                    // Since the backtrace is printed, the first function is always
-                    // core::fmt::write. Skip 1 so it is excluded and doesn't bloat the output.
-                    for (i, backtrace_res) in iter.skip(1).enumerate() {
-                        match backtrace_res {
-                            BacktraceItem::InvalidFramePointer(addr) => {
-                                fmt_res = writeln!(
-                                    f,
-                                    "      {:>2}. ERROR! \
-                                    Encountered invalid frame pointer ({}) during backtrace",
-                                    i + 1,
-                                    addr
-                                );
-                            }
-                            BacktraceItem::InvalidLink(addr) => {
-                                fmt_res = writeln!(
-                                    f,
-                                    "      {:>2}. ERROR! \
-                                    Link address ({}) is not contained in kernel .text section",
-                                    i + 1,
-                                    addr
-                                );
-                            }
-                            BacktraceItem::Link(addr) => {
-                                fmt_res = writeln!(
-                                    f,
-                                    "      {:>2}. {:016x} | {:<50}",
-                                    i + 1,
-                                    addr.as_usize(),
-                                    match symbols::lookup_symbol(addr) {
-                                        Some(sym) => sym.name(),
-                                        _ => "Symbol not found",
-                                    }
-                                )
-                            }
-                        };
-
-                        if fmt_res.is_err() {
-                            break;
-                        }
-                    }
-                }
-            };
-
-        arch_backtrace::backtrace(trace_formatter);
+        arch_backtrace::backtrace_of(self.0, |maybe_iter| fmt_res = write_items(f, maybe_iter, 2));
         fmt_res?;
 
-        writeln!(
-            f,
-            "      ----------------------------------------------------------------------------------------------"
-        )
+        writeln!(f, "{}", SEPARATOR)
     }
 }
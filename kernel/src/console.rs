@@ -7,6 +7,11 @@
 mod buffer_console;
 
 use crate::synchronization;
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -15,6 +20,9 @@ use crate::synchronization;
 /// Console interfaces.
 pub mod interface;
 
+/// Future returned by `read_char()`.
+pub struct ReadChar;
+
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
@@ -47,3 +55,16 @@ pub fn register_console(new_console: &'static (dyn interface::All + Sync)) {
 pub fn console() -> &'static dyn interface::All {
     CUR_CONSOLE.read(|con| *con)
 }
+
+/// Return a future that completes with the next character received by the console.
+pub fn read_char() -> ReadChar {
+    ReadChar
+}
+
+impl Future for ReadChar {
+    type Output = char;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<char> {
+        console().poll_read_char(cx)
+    }
+}
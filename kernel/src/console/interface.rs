@@ -1,4 +1,7 @@
-use core::fmt;
+use core::{
+    fmt,
+    task::{Context, Poll},
+};
 
 /// Console write functions.
 pub trait Write {
@@ -22,6 +25,12 @@ pub trait Read {
         ' '
     }
 
+    /// Read a single character if one is available. Otherwise, arrange for `cx` to be woken once
+    /// one arrives.
+    fn poll_read_char(&self, _cx: &mut Context<'_>) -> Poll<char> {
+        Poll::Pending
+    }
+
     /// Clear RX buffers, if any.
     fn clear_rx(&self);
 }
@@ -13,6 +13,11 @@ mod arch_boot;
 #[path = "aarch64/smp.rs"]
 mod arch_smp;
 
+pub mod per_cpu;
+
+/// Number of cores the kernel supports. Sizes all per-core data.
+pub const NR_CPUS: usize = 4;
+
 /// Used by `arch` code to find the early boot core.
 #[no_mangle]
 #[link_section = ".text._start_arguments"]
@@ -26,4 +31,4 @@ pub use arch_smp::core_id;
 //--------------------------------------------------------------------------------------------------
 // Architectural Public Reexports
 //--------------------------------------------------------------------------------------------------
-pub use arch_cpu::{nop, wait_forever};
+pub use arch_cpu::{jump_with_stack, nop, wait_for_interrupt, wait_forever};
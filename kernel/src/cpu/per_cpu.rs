@@ -0,0 +1,90 @@
+//! Per-core variables.
+//!
+//! A `PerCpu<T>` holds one instance of `T` for every core the kernel supports, `NR_CPUS` in total.
+//! Cores usually only touch their own instance. Others can still be reached by index, e.g. to look
+//! at the run queue of another core.
+
+use super::{core_id, NR_CPUS};
+use crate::scheduler::preempt;
+use core::{ops::Index, slice};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// One instance of `T` per core.
+pub struct PerCpu<T> {
+    data: [T; NR_CPUS],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<T> PerCpu<T> {
+    /// Create an instance. Usually done through `per_cpu!`.
+    pub const fn new(data: [T; NR_CPUS]) -> Self {
+        Self { data }
+    }
+
+    /// Number of instances, which is `NR_CPUS`.
+    pub const fn len(&self) -> usize {
+        NR_CPUS
+    }
+
+    /// Whether there are no instances, which is never the case.
+    pub const fn is_empty(&self) -> bool {
+        NR_CPUS == 0
+    }
+
+    /// Iterate over the instances of all cores, ordered by core ID.
+    pub fn iter(&self) -> slice::Iter<'_, T> {
+        self.data.iter()
+    }
+
+    /// Grants the closure access to the executing core's instance.
+    ///
+    /// Preemption is disabled meanwhile, so the thread cannot move to another core before the
+    /// closure returns.
+    pub fn with_local<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        preempt::without_preemption(|| f(&self.data[core_id::<usize>()]))
+    }
+}
+
+impl<T> Index<usize> for PerCpu<T> {
+    type Output = T;
+
+    fn index(&self, core: usize) -> &T {
+        &self.data[core]
+    }
+}
+
+impl<'a, T> IntoIterator for &'a PerCpu<T> {
+    type Item = &'a T;
+    type IntoIter = slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Declare per-core statics, one instance per core, each initialized with the same constant
+/// expression.
+///
+/// ```ignore
+/// per_cpu! {
+///     static PICKS: AtomicUsize = AtomicUsize::new(0);
+/// }
+///
+/// PICKS.with_local(|picks| picks.fetch_add(1, Ordering::Relaxed));
+/// ```
+#[macro_export]
+macro_rules! per_cpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::cpu::per_cpu::PerCpu<$t> =
+                $crate::cpu::per_cpu::PerCpu::new([const { $init }; $crate::cpu::NR_CPUS]);
+        )*
+    };
+}
@@ -6,7 +6,7 @@
 
 use crate::{
     exception, info,
-    synchronization::{interface::ReadWriteEx, InitStateLock},
+    synchronization::{interface::ReadWriteEx, RwSpinLock},
 };
 use alloc::vec::Vec;
 use core::fmt;
@@ -36,7 +36,7 @@ pub struct DriverManager<T>
 where
     T: 'static,
 {
-    descriptors: InitStateLock<Vec<DeviceDriverDescriptor<T>>>,
+    descriptors: RwSpinLock<Vec<DeviceDriverDescriptor<T>>>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -76,7 +76,7 @@ where
     /// Create an instance.
     pub const fn new() -> Self {
         Self {
-            descriptors: InitStateLock::new(Vec::new()),
+            descriptors: RwSpinLock::new(Vec::new()),
         }
     }
 
@@ -17,7 +17,10 @@ pub use gicv2::*;
 pub use bcm2711_gpio::*;
 pub use bcm2711_pl011_uart::*;
 
-use self::{mailbox::Mailbox, sgi::SGIHandler};
+use self::{
+    mailbox::Mailbox,
+    sgi::{CallHandler, SGIHandler, WatchdogHandler},
+};
 
 use super::{exception, memory::map::mmio};
 use crate::{
@@ -38,6 +41,8 @@ use core::{
 static mut PL011_UART: MaybeUninit<PL011Uart> = MaybeUninit::uninit();
 static mut GPIO: MaybeUninit<GPIO> = MaybeUninit::uninit();
 static mut SGI_HANDLER: MaybeUninit<SGIHandler> = MaybeUninit::uninit();
+static CALL_HANDLER: CallHandler = CallHandler::new();
+static WATCHDOG_HANDLER: WatchdogHandler = WatchdogHandler::new();
 static mut INTERRUPT_CONTROLLER: MaybeUninit<GICv2> = MaybeUninit::uninit();
 static mut MAILBOX: MaybeUninit<Mailbox> = MaybeUninit::uninit();
 
@@ -131,6 +136,20 @@ unsafe fn driver_sgi() -> Result<(), &'static str> {
     );
     generic_driver::driver_manager().register_driver(sgi_descriptor);
 
+    let call_descriptor = generic_driver::DeviceDriverDescriptor::new(
+        &CALL_HANDLER,
+        None,
+        Some(exception::asynchronous::irq_map::SGI_10),
+    );
+    generic_driver::driver_manager().register_driver(call_descriptor);
+
+    let watchdog_descriptor = generic_driver::DeviceDriverDescriptor::new(
+        &WATCHDOG_HANDLER,
+        None,
+        Some(exception::asynchronous::irq_map::SGI_11),
+    );
+    generic_driver::driver_manager().register_driver(watchdog_descriptor);
+
     Ok(())
 }
 /// Function needs to ensure that driver registration happens only after correct instantiation.
@@ -18,7 +18,12 @@ use crate::{
     synchronization::IRQSafeLock,
     time::time_manager,
 };
-use core::{fmt, time::Duration};
+use alloc::{collections::VecDeque, vec::Vec};
+use core::{
+    fmt, mem,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
 
 use spin::mutex::SpinMutex;
 use tock_registers::{
@@ -218,6 +223,9 @@ register_structs! {
 /// Abstraction for the associated MMIO registers.
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
+/// Received characters kept for readers. Further characters are dropped while it is full.
+const RX_BUFFER_SIZE: usize = 256;
+
 #[derive(PartialEq)]
 enum BlockingMode {
     Blocking,
@@ -228,6 +236,10 @@ struct PL011UartInner {
     registers: Registers,
     chars_written: usize,
     chars_read: usize,
+    /// Characters taken out of the RX FIFO by the IRQ handler, but not read yet.
+    rx_buffer: VecDeque<char>,
+    /// Tasks waiting for the next character.
+    rx_wakers: Vec<Waker>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -254,6 +266,8 @@ impl PL011UartInner {
             registers: Registers::new(mmio_start_addr),
             chars_written: 0,
             chars_read: 0,
+            rx_buffer: VecDeque::new(),
+            rx_wakers: Vec::new(),
         }
     }
 
@@ -346,19 +360,10 @@ impl PL011UartInner {
         }
     }
 
-    /// Retrieve a character.
-    fn read_char_converting(&mut self, blocking_mode: BlockingMode) -> Option<char> {
-        // If RX FIFO is empty,
+    /// Take a character out of the RX FIFO, if there is one.
+    fn receive(&mut self) -> Option<char> {
         if self.registers.FR.matches_all(FR::RXFE::SET) {
-            // immediately return in non-blocking mode.
-            if blocking_mode == BlockingMode::NonBlocking {
-                return None;
-            }
-
-            // Otherwise, wait until a char was received.
-            while self.registers.FR.matches_all(FR::RXFE::SET) {
-                cpu::nop();
-            }
+            return None;
         }
 
         // Read one character.
@@ -369,6 +374,36 @@ impl PL011UartInner {
             ret = '\n'
         }
 
+        Some(ret)
+    }
+
+    /// Retrieve a character.
+    ///
+    /// Non-blocking reads take the characters buffered by the IRQ handler first. Blocking reads
+    /// drop them and wait for a character that arrives after the call, so that they never return
+    /// stale input.
+    fn read_char_converting(&mut self, blocking_mode: BlockingMode) -> Option<char> {
+        if blocking_mode == BlockingMode::Blocking {
+            self.rx_buffer.clear();
+        }
+
+        let ret = if let Some(c) = self.rx_buffer.pop_front() {
+            c
+        } else {
+            loop {
+                if let Some(c) = self.receive() {
+                    break c;
+                }
+
+                // Immediately return in non-blocking mode, otherwise wait until a char was
+                // received.
+                if blocking_mode == BlockingMode::NonBlocking {
+                    return None;
+                }
+                cpu::nop();
+            }
+        };
+
         // Update statistics.
         self.chars_read += 1;
 
@@ -474,6 +509,7 @@ impl console::interface::Write for PL011Uart {
 }
 
 impl console::interface::Read for PL011Uart {
+    /// Drops input buffered by the IRQ handler and waits for a fresh character.
     fn read_char(&self) -> char {
         self.inner.lock(|inner| {
             inner
@@ -483,6 +519,23 @@ impl console::interface::Read for PL011Uart {
         })
     }
 
+    fn poll_read_char(&self, cx: &mut Context<'_>) -> Poll<char> {
+        self.inner.lock(|inner| {
+            let mut l = inner.lock();
+
+            match l.read_char_converting(BlockingMode::NonBlocking) {
+                Some(c) => Poll::Ready(c),
+                None => {
+                    // Registered with the lock held, so the IRQ handler cannot miss it.
+                    if !l.rx_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                        l.rx_wakers.push(cx.waker().clone());
+                    }
+                    Poll::Pending
+                }
+            }
+        })
+    }
+
     fn clear_rx(&self) {
         // Read from the RX FIFO until it is indicating empty.
         while self
@@ -507,7 +560,7 @@ impl console::interface::All for PL011Uart {}
 
 impl exception::asynchronous::interface::IRQHandler for PL011Uart {
     fn handle(&self, _e: &mut ExceptionContext) -> Result<(), &'static str> {
-        self.inner.lock(|inner| {
+        let wakers = self.inner.lock(|inner| {
             let mut l = inner.lock();
             let pending = l.registers.MIS.extract();
 
@@ -515,14 +568,26 @@ impl exception::asynchronous::interface::IRQHandler for PL011Uart {
             l.registers.ICR.write(ICR::ALL::CLEAR);
 
             // Check for any kind of RX interrupt.
-            if pending.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET) {
-                // Echo any received characters.
-                while let Some(c) = l.read_char_converting(BlockingMode::NonBlocking) {
-                    l.write_char(c)
+            if !pending.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET) {
+                return Vec::new();
+            }
+
+            // Echo any received characters and keep them for readers.
+            while let Some(c) = l.receive() {
+                l.write_char(c);
+                if l.rx_buffer.len() < RX_BUFFER_SIZE {
+                    l.rx_buffer.push_back(c);
                 }
             }
+
+            mem::take(&mut l.rx_wakers)
         });
 
+        // Woken without the lock held, since wakers take locks of their own.
+        for waker in wakers {
+            waker.wake();
+        }
+
         Ok(())
     }
 }
@@ -13,8 +13,7 @@ use crate::{
     drivers::common::BoundedUsize,
     exception::{self, arch_exception::ExceptionContext},
     memory::{Address, Virtual},
-    synchronization,
-    synchronization::InitStateLock,
+    synchronization::rcu::Rcu,
 };
 
 use alloc::vec::Vec;
@@ -40,8 +39,10 @@ pub struct GICv2 {
     /// The CPU Interface.
     gicc: gicc::GICC,
 
-    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
-    handler_table: InitStateLock<HandlerTable>,
+    /// Stores registered IRQ handlers. Handlers may be added after kernel init as well.
+    ///
+    /// Protected by RCU, so dispatching an IRQ never waits for a handler being registered.
+    handler_table: Rcu<HandlerTable>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -68,20 +69,23 @@ impl GICv2 {
         Self {
             gicd: gicd::GICD::new(gicd_mmio_start_addr),
             gicc: gicc::GICC::new(gicc_mmio_start_addr),
-            handler_table: InitStateLock::new(Vec::new()),
+            handler_table: Rcu::empty(),
         }
     }
 
     pub fn send_sgi(&self, int_num: IRQNumber, cpu: u8) {
-        self.gicd.send_sgi(int_num, cpu)
+        self.gicd.send_sgi(int_num, 1 << cpu)
+    }
+
+    /// Send an SGI to the cores whose bits are set in `targets`.
+    pub fn send_sgi_to(&self, int_num: IRQNumber, targets: u8) {
+        self.gicd.send_sgi(int_num, targets)
     }
 }
 
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
-use synchronization::interface::ReadWriteEx;
-
 use super::INTERRUPT_CONTROLLER;
 
 impl driver::interface::DeviceDriver for GICv2 {
@@ -92,8 +96,12 @@ impl driver::interface::DeviceDriver for GICv2 {
     }
 
     unsafe fn init(&self) -> Result<(), &'static str> {
-        self.handler_table
-            .write(|table| table.resize(IRQNumber::MAX_INCLUSIVE + 1, None));
+        self.handler_table.update(|table| {
+            let mut table = table.cloned().unwrap_or_default();
+            table.resize(IRQNumber::MAX_INCLUSIVE + 1, None);
+
+            table
+        });
 
         if cpu::BOOT_CORE_ID == cpu::core_id() {
             self.gicd.boot_core_init();
@@ -113,17 +121,22 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
         &self,
         irq_handler_descriptor: exception::asynchronous::IRQHandlerDescriptor<Self::IRQNumberType>,
     ) -> Result<(), &'static str> {
-        self.handler_table.write(|table| {
-            let irq_number = irq_handler_descriptor.number().get();
+        let irq_number = irq_handler_descriptor.number().get();
+        let mut result = Ok(());
+
+        self.handler_table.update(|table| {
+            let mut table = table.cloned().unwrap_or_default();
 
             if table[irq_number].is_some() {
-                return Err("IRQ handler already registered");
+                result = Err("IRQ handler already registered");
+            } else {
+                table[irq_number] = Some(irq_handler_descriptor);
             }
 
-            table[irq_number] = Some(irq_handler_descriptor);
+            table
+        });
 
-            Ok(())
-        })
+        result
     }
 
     fn enable(&self, irq_number: &Self::IRQNumberType) {
@@ -145,20 +158,24 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
         }
 
         // Call the IRQ handler. Panic if there is none.
-        self.handler_table.read(|table| {
-            let core: u8 = core_id();
-
-            match table[irq_number] {
-                None => panic!(
-                    "No handler registered for IRQ {} on Core{}",
-                    irq_number, core
-                ),
-                Some(descriptor) => {
-                    // Call the IRQ handler. Panics on failure.
-                    descriptor.handler().handle(e).expect("Error handling IRQ");
-                }
+        //
+        // The descriptor is copied out of the table, because the handler might switch threads,
+        // which must not happen in a read-side critical section.
+        let descriptor = self
+            .handler_table
+            .read(|table| table.and_then(|table| table[irq_number]));
+
+        match descriptor {
+            None => panic!(
+                "No handler registered for IRQ {} on Core{}",
+                irq_number,
+                core_id::<u8>()
+            ),
+            Some(descriptor) => {
+                // Call the IRQ handler. Panics on failure.
+                descriptor.handler().handle(e).expect("Error handling IRQ");
             }
-        });
+        }
 
         // Signal completion of handling.
         self.gicc.mark_comleted(irq_number as u32, ic);
@@ -170,7 +187,7 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
         info!("      Peripheral handler:");
 
         self.handler_table.read(|table| {
-            for (i, opt) in table.iter().skip(32).enumerate() {
+            for (i, opt) in table.into_iter().flatten().skip(32).enumerate() {
                 if let Some(handler) = opt {
                     info!("            {: >3}. {}", i + 32, handler.name());
                 }
@@ -159,12 +159,13 @@ impl GICD {
         self.banked_registers.ITARGETSR[0].read(ITARGETSR::Offset0)
     }
 
-    pub fn send_sgi(&self, sgi_num: IRQNumber, cpu: u8) {
+    /// Send SGI `sgi_num` to the cores whose bits are set in `targets`.
+    pub fn send_sgi(&self, sgi_num: IRQNumber, targets: u8) {
         let sgi_reg = &self.banked_registers.SGIR;
         sgi_reg.write(
             SGIR::TargetListFilter.val(0)
                 + SGIR::SgiIntID.val(sgi_num.get() as u32)
-                + SGIR::CPUTargetList.val(1 << cpu),
+                + SGIR::CPUTargetList.val(targets as u32),
         );
     }
 
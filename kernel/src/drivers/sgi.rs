@@ -4,8 +4,8 @@ use crate::{
     exception::{self, arch_exception::ExceptionContext, asynchronous::IRQNumber},
     info,
     memory::{Address, Virtual},
-    scheduler::reschedule_from_context,
-    synchronization,
+    scheduler::{reschedule_from_context, watchdog},
+    smp, synchronization,
     synchronization::IRQSafeLock,
     time::time_manager,
 };
@@ -62,3 +62,91 @@ impl exception::asynchronous::interface::IRQHandler for SGIHandler {
         Ok(())
     }
 }
+
+/// Runs the cross-core function calls queued for the interrupted core.
+pub struct CallHandler {}
+
+impl CallHandler {
+    pub const COMPATIBLE: &'static str = "SGI Call Handler";
+
+    pub const fn new() -> Self {
+        Self {}
+    }
+}
+
+impl driver::interface::DeviceDriver for CallHandler {
+    type IRQNumberType = IRQNumber;
+
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+        irq_number: &Self::IRQNumberType,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor};
+
+        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self);
+
+        irq_manager().register_handler(descriptor)?;
+        irq_manager().enable(irq_number);
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for CallHandler {
+    fn handle(&self, _e: &mut ExceptionContext) -> Result<(), &'static str> {
+        smp::handle_calls();
+        Ok(())
+    }
+}
+
+/// Prints the state of the interrupted core for the lockup watchdog.
+pub struct WatchdogHandler {}
+
+impl WatchdogHandler {
+    pub const COMPATIBLE: &'static str = "SGI Watchdog Handler";
+
+    pub const fn new() -> Self {
+        Self {}
+    }
+}
+
+impl driver::interface::DeviceDriver for WatchdogHandler {
+    type IRQNumberType = IRQNumber;
+
+    fn compatible(&self) -> &'static str {
+        Self::COMPATIBLE
+    }
+
+    unsafe fn init(&self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn register_and_enable_irq_handler(
+        &'static self,
+        irq_number: &Self::IRQNumberType,
+    ) -> Result<(), &'static str> {
+        use exception::asynchronous::{irq_manager, IRQHandlerDescriptor};
+
+        let descriptor = IRQHandlerDescriptor::new(*irq_number, Self::COMPATIBLE, self);
+
+        irq_manager().register_handler(descriptor)?;
+        irq_manager().enable(irq_number);
+
+        Ok(())
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for WatchdogHandler {
+    fn handle(&self, e: &mut ExceptionContext) -> Result<(), &'static str> {
+        watchdog::dump(e);
+        Ok(())
+    }
+}
@@ -37,6 +37,10 @@ pub mod irq_map {
     pub const PL011_UART: IRQNumber = IRQNumber::new(153);
 
     pub const SGI_9: IRQNumber = IRQNumber::new(9);
+    /// Cross-core function calls, see `smp::call_on_many()`.
+    pub const SGI_10: IRQNumber = IRQNumber::new(10);
+    /// Lockup reports, see `scheduler::watchdog`.
+    pub const SGI_11: IRQNumber = IRQNumber::new(11);
 }
 /// Interrupt descriptor.
 #[derive(Copy, Clone)]
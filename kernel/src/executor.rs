@@ -0,0 +1,162 @@
+//! Cooperative executor for kernel tasks.
+//!
+//! A task is a `Future` without a stack of its own. Thousands of them can wait for a timeout or for
+//! console input while sharing the few executor threads started through `start()`. An executor
+//! thread polls ready tasks one after the other and blocks once none is left.
+//!
+//! Waking a task puts it back into the ready queue and wakes an executor thread. Wakers may be
+//! called from IRQ handlers, which is how `TimeManager::sleep()` and `console::read_char()`
+//! resume their tasks.
+
+use crate::{
+    synchronization::{interface::Mutex, IRQSafeLock, SpinLock, WaitQueue},
+    thread,
+};
+use alloc::{boxed::Box, collections::LinkedList, sync::Arc, task::Wake};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Waker},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+type BoxedFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Task {
+    /// `None` once the future has completed.
+    future: SpinLock<Option<BoxedFuture>>,
+
+    /// Set while the task is in the ready queue, so that it is queued at most once.
+    queued: AtomicBool,
+}
+
+static EXECUTOR: Executor = Executor::new();
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Runs tasks on the threads that call `run()`.
+pub struct Executor {
+    ready: IRQSafeLock<SpinLock<LinkedList<Arc<Task>>>>,
+    idle: WaitQueue,
+    tasks: AtomicUsize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        executor().schedule(self);
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        executor().schedule(self.clone());
+    }
+}
+
+impl Executor {
+    const fn new() -> Self {
+        Self {
+            ready: IRQSafeLock::new(SpinLock::new(LinkedList::new())),
+            idle: WaitQueue::new(),
+            tasks: AtomicUsize::new(0),
+        }
+    }
+
+    /// Put `task` into the ready queue unless it is there already.
+    fn schedule(&self, task: Arc<Task>) {
+        if task.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        self.ready
+            .lock(|spin_lock| spin_lock.lock(|ready| ready.push_back(task)));
+        self.idle.notify_one();
+    }
+
+    fn pop(&self) -> Option<Arc<Task>> {
+        self.ready
+            .lock(|spin_lock| spin_lock.lock(|ready| ready.pop_front()))
+    }
+
+    fn has_ready(&self) -> bool {
+        self.ready
+            .lock(|spin_lock| spin_lock.lock(|ready| !ready.is_empty()))
+    }
+
+    fn poll(&self, task: Arc<Task>) {
+        // Cleared first, so that a wakeup during the poll queues the task again.
+        task.queued.store(false, Ordering::Release);
+
+        let waker = Waker::from(task.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        task.future.lock(|future| {
+            // A task may be woken after it completed.
+            let done = future
+                .as_mut()
+                .map_or(false, |f| f.as_mut().poll(&mut cx).is_ready());
+
+            if done {
+                *future = None;
+                self.tasks.fetch_sub(1, Ordering::Relaxed);
+            }
+        });
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Executor {
+    /// Add a task running `future`.
+    pub fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) {
+        let task = Arc::new(Task {
+            future: SpinLock::new(Some(Box::pin(future))),
+            queued: AtomicBool::new(false),
+        });
+
+        self.tasks.fetch_add(1, Ordering::Relaxed);
+        self.schedule(task);
+    }
+
+    /// Number of tasks that have not completed yet.
+    pub fn task_count(&self) -> usize {
+        self.tasks.load(Ordering::Relaxed)
+    }
+
+    /// Poll ready tasks forever. Must be called from a thread.
+    pub fn run(&self) -> ! {
+        loop {
+            match self.pop() {
+                Some(task) => self.poll(task),
+                None => self.idle.wait_if(|| !self.has_ready()),
+            }
+        }
+    }
+}
+
+/// Return a reference to the kernel's executor.
+pub fn executor() -> &'static Executor {
+    &EXECUTOR
+}
+
+/// Add a task running `future` to the kernel's executor.
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    executor().spawn(future)
+}
+
+/// Start `threads` threads running the kernel's executor.
+pub fn start(threads: usize) {
+    for _ in 0..threads {
+        thread::spawn("executor", || executor().run());
+    }
+}
@@ -32,7 +32,7 @@ use crate::drivers::{get_gic, mailbox, GICv2, IRQNumber};
 use crate::exception::asynchronous::irq_map;
 use crate::scheduler::{reschedule_from_context, SLEEPING};
 use crate::synchronization::interface::Mutex;
-use crate::thread::{thread, wait_thread, Thread, __switch_to, print_t, sleep};
+use crate::thread::{thread, wait_thread, Priority, Thread, __switch_to, print_t, sleep};
 use aarch64_cpu::registers::{SPSel, SP, SP_EL0};
 use alloc::boxed::Box;
 use exception::arch_exception::ExceptionContext;
@@ -40,7 +40,7 @@ use tock_registers::interfaces::Readable;
 
 use crate::{
     board::version,
-    cpu::{core_id, wait_forever},
+    cpu::{core_id, wait_forever, NR_CPUS},
     exception::asynchronous::{local_irq_mask_save, local_irq_restore},
     scheduler::{CURRENT, RUNNING},
     smp::start_core,
@@ -61,6 +61,7 @@ pub mod cpu;
 pub mod driver;
 pub mod drivers;
 pub mod exception;
+pub mod executor;
 pub mod memory;
 pub mod print;
 pub mod random;
@@ -72,7 +73,6 @@ pub mod thread;
 pub mod time;
 
 static THREADS_NUMBER: usize = 10;
-static TICK_MS: usize = 5;
 /// Early init code.
 ///
 /// When this code runs, virtual memory is already enabled.
@@ -84,8 +84,15 @@ static TICK_MS: usize = 5;
 #[no_mangle]
 unsafe fn kernel_init() -> ! {
     exception::handling_init();
+    thread::tls::init();
     memory::init();
 
+    // Round-robin is the built-in default.
+    #[cfg(feature = "sched_random")]
+    scheduler::register_scheduling_policy(&scheduler::random_picker::RANDOM_PICKER);
+    #[cfg(feature = "sched_mlfq")]
+    scheduler::register_scheduling_policy(&scheduler::mlfq::MLFQ);
+
     // Initialize the timer subsystem.
     if let Err(x) = time::init() {
         panic!("Error initializing timer subsystem: {}", x);
@@ -101,6 +108,9 @@ unsafe fn kernel_init() -> ! {
 
     memory::mmu::kernel_add_mapping_records_for_precomputed();
 
+    // Other cores may send calls to the boot core from now on.
+    smp::mark_online();
+
     // Unmask interrupts on the boot CPU core.
     exception::asynchronous::local_irq_unmask();
 
@@ -138,6 +148,8 @@ fn kernel_main() -> ! {
 
     info!("Kernel heap:");
     memory::heap_alloc::kernel_heap_allocator().print_usage();
+
+    info!("Scheduling policy: {}", scheduler::scheduling_policy().name());
     info!("Echoing input now");
 
     state::state_manager().transition_to_multi_core_main();
@@ -148,43 +160,27 @@ fn kernel_main() -> ! {
 
     let core: usize = core_id();
 
-    let entry_point = thread as *const () as u64;
-    let idle_thread_ep = wait_thread as *const () as u64;
-    let print_t_ep = print_t as *const () as u64;
-
-    //PID {0, 1, 2, 3} are the idle threads for each core
-    for i in 0..=3 {
-        let idle_thread: Thread = Thread::new(idle_thread_ep);
-        RUNNING[i].add(idle_thread);
+    // PIDs 0..NR_CPUS are the idle threads for each core
+    for i in 0..NR_CPUS {
+        thread::spawn_with(Some(i), Priority::IDLE, "idle", wait_thread);
     }
 
-    for i in 0..=3 {
+    for i in 0..NR_CPUS {
         for _ in 0..THREADS_NUMBER {
-            let new_thread = Thread::new(entry_point);
-            RUNNING[i].add(new_thread);
+            thread::spawn_on(i, "worker", thread);
         }
     }
-    let print_t_new = Thread::new(print_t_ep);
-    RUNNING[0].add(print_t_new);
+    thread::spawn_with(Some(0), Priority::HIGH, "print_t", print_t);
+    executor::start(2);
 
     info!("Enabling other cores");
-    (1..=3).for_each(|i| unsafe { start_core(i) });
+    (1..NR_CPUS).for_each(|i| unsafe { start_core(i as u8) });
     //time_manager().spin_for(Duration::from_secs(2));
 
     info!("Running Thread list for Core{}:\n{}", core, RUNNING[core]);
-    //Setting the scheduler timer interrupt
-    time_manager().set_timeout_periodic(
-        Duration::from_millis(TICK_MS as u64),
-        Box::new(|ec| {
-            //println!("Scheduler called!");
-            unsafe {
-                get_gic().send_sgi(irq_map::SGI_9, 3);
-                get_gic().send_sgi(irq_map::SGI_9, 2);
-                get_gic().send_sgi(irq_map::SGI_9, 1);
-                //get_gic().send_sgi(irq_map::SGI_9, 0);
-            };
-            reschedule_from_context(ec);
-        }),
-    );
+    scheduler::watchdog::start();
+
+    // Enter the scheduler. From now on, the tick only runs while some core has threads to preempt.
+    scheduler::kick(core);
     wait_forever();
 }
@@ -6,6 +6,7 @@
 pub mod heap_alloc;
 pub mod map;
 pub mod mmu;
+pub mod stack_alloc;
 use crate::{common, memory};
 use core::{
     fmt,
@@ -35,6 +36,9 @@ extern "Rust" {
     static __mmio_remap_start: UnsafeCell<()>;
     static __mmio_remap_end_exclusive: UnsafeCell<()>;
 
+    static __thread_stacks_start: UnsafeCell<()>;
+    static __thread_stacks_end_exclusive: UnsafeCell<()>;
+
     pub static __core_activation_address: UnsafeCell<()>;
 
     static __boot_core_stack_start: UnsafeCell<()>;
@@ -117,6 +121,26 @@ fn mmio_remap_size() -> usize {
     unsafe { (__mmio_remap_end_exclusive.get() as usize) - (__mmio_remap_start.get() as usize) }
 }
 
+/// Start page address of the thread stacks reservation.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn virt_thread_stacks_start() -> PageAddress<Virtual> {
+    PageAddress::from(unsafe { __thread_stacks_start.get() as usize })
+}
+
+/// Size of the thread stacks reservation.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn thread_stacks_size() -> usize {
+    unsafe { (__thread_stacks_end_exclusive.get() as usize) - (__thread_stacks_start.get() as usize) }
+}
+
 /// Start page address of the boot core's stack.
 #[inline(always)]
 fn virt_boot_core_stack_start() -> PageAddress<Virtual> {
@@ -248,9 +272,10 @@ impl<ATYPE: AddressType> Sub<Address<ATYPE>> for Address<ATYPE> {
 }
 
 impl Address<Virtual> {
-    /// Checks if the address is part of the boot core stack region.
+    /// Checks if the address is part of the boot core stack region or of a thread stack.
     pub fn is_valid_stack_addr(&self) -> bool {
         memory::mmu::virt_boot_core_stack_region().contains(*self)
+            || memory::mmu::virt_thread_stacks_region().contains(*self)
     }
 
     /// Checks if the address is part of the kernel code region.
@@ -292,4 +317,5 @@ impl fmt::Display for Address<Virtual> {
 pub fn init() {
     mmu::kernel_init_mmio_va_allocator();
     heap_alloc::kernel_init_heap_allocator();
+    stack_alloc::kernel_init_stack_allocator();
 }
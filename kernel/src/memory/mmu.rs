@@ -18,13 +18,13 @@ use crate::{
     memory::{Address, Physical, Virtual},
     synchronization::{self, interface::Mutex},
 };
-use core::{fmt, num::NonZeroUsize};
+use core::{cell::UnsafeCell, fmt, num::NonZeroUsize};
 
 pub use types::*;
 
 use crate::{
     memory::mmu::{self as generic_mmu },
-    synchronization::InitStateLock,
+    synchronization::{InitStateLock, IRQSafeLock},
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -75,6 +75,9 @@ pub type KernelVirtAddrSpace = AddressSpace<{ kernel_virt_addr_space_size() }>;
 static KERNEL_TABLES: InitStateLock<KernelTranslationTable> =
     InitStateLock::new(KernelTranslationTable::new_for_precompute());
 
+/// Serializes the changes the thread stack allocator makes to `KERNEL_TABLES` after kernel init.
+static RUNTIME_MAPPING_LOCK: IRQSafeLock<()> = IRQSafeLock::new(());
+
 /// This value is needed during early boot for MMU setup.
 ///
 /// This will be patched to the correct value by the "translation table tool" after linking. This
@@ -169,6 +172,16 @@ pub fn virt_boot_core_stack_region() -> MemoryRegion<Virtual> {
     MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
 }
 
+/// The pages reserved for thread stacks.
+pub fn virt_thread_stacks_region() -> MemoryRegion<Virtual> {
+    let num_pages = size_to_num_pages(super::thread_stacks_size());
+
+    let start_page_addr = super::virt_thread_stacks_start();
+    let end_exclusive_page_addr = start_page_addr.checked_offset(num_pages as isize).unwrap();
+
+    MemoryRegion::new(start_page_addr, end_exclusive_page_addr)
+}
+
 /// Return a reference to the kernel's translation tables.
 pub fn kernel_translation_tables() -> &'static InitStateLock<KernelTranslationTable> {
     &KERNEL_TABLES
@@ -387,6 +400,62 @@ pub unsafe fn kernel_map_mmio(
     Ok(virt_addr + offset_into_start_page)
 }
 
+/// Change the kernel's translation tables after kernel init, with `RUNTIME_MAPPING_LOCK` held.
+///
+/// # Safety
+///
+/// - `f` must only touch entries of the thread stack range, which nothing but the thread stack
+///   allocator reads or writes.
+unsafe fn with_runtime_tables<R>(f: impl FnOnce(&mut KernelTranslationTable) -> R) -> R {
+    RUNTIME_MAPPING_LOCK.lock(|_| {
+        // `InitStateLock` is a transparent wrapper around an `UnsafeCell`, see `KERNEL_TABLES`.
+        let cell = &*(&KERNEL_TABLES as *const InitStateLock<KernelTranslationTable>
+            as *const UnsafeCell<KernelTranslationTable>);
+
+        f(&mut *cell.get())
+    })
+}
+
+/// Map a thread stack in the kernel's translation tables after kernel init.
+///
+/// No mapping record is added, since stacks come and go.
+///
+/// # Safety
+///
+/// - See `map_at()`.
+/// - `virt_region` must lie in the thread stack range and must not be mapped yet.
+pub(super) unsafe fn kernel_map_at_runtime(
+    virt_region: &MemoryRegion<Virtual>,
+    phys_region: &MemoryRegion<Physical>,
+    attr: &AttributeFields,
+) -> Result<(), &'static str> {
+    if !virt_thread_stacks_region().contains(virt_region.start_addr()) {
+        return Err("Runtime mappings are limited to thread stacks");
+    }
+
+    with_runtime_tables(|tables| tables.map_at(virt_region, phys_region, attr))?;
+
+    arch_mmu::mmu().invalidate_tlb(virt_region);
+
+    Ok(())
+}
+
+/// Remove a mapping created by `kernel_map_at_runtime()`.
+///
+/// # Safety
+///
+/// - `virt_region` must have been mapped by `kernel_map_at_runtime()`.
+/// - The region must not be accessed anymore by any core.
+pub(super) unsafe fn kernel_unmap_at_runtime(
+    virt_region: &MemoryRegion<Virtual>,
+) -> Result<(), &'static str> {
+    with_runtime_tables(|tables| tables.unmap_at(virt_region))?;
+
+    arch_mmu::mmu().invalidate_tlb(virt_region);
+
+    Ok(())
+}
+
 /// Try to translate a kernel virtual page address to a physical page address.
 ///
 /// Will only succeed if there exists a valid mapping for the input page.
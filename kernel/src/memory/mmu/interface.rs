@@ -14,4 +14,8 @@ pub trait MMU {
 
     /// Returns true if the MMU is enabled, false otherwise.
     fn is_enabled(&self) -> bool;
+
+    /// Make changed translation table entries of the given region visible to all cores, dropping
+    /// stale TLB entries.
+    fn invalidate_tlb(&self, virt_region: &MemoryRegion<Virtual>);
 }
@@ -51,6 +51,15 @@ pub mod interface {
             attr: &AttributeFields,
         ) -> Result<(), &'static str>;
 
+        /// Remove the mapping of the given virtual memory region.
+        ///
+        /// # Safety
+        ///
+        /// - The region must not be in use anymore. TLB entries still referring to it must be
+        ///   invalidated by the caller.
+        unsafe fn unmap_at(&mut self, virt_region: &MemoryRegion<Virtual>)
+            -> Result<(), &'static str>;
+
         /// Try to translate a virtual page address to a physical page address.
         ///
         /// Will only succeed if there exists a valid mapping for the input page.
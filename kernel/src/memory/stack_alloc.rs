@@ -0,0 +1,241 @@
+//! Thread stack allocation.
+//!
+//! Stacks live in a virtual range that the linker script reserves for this purpose. Every stack is
+//! preceded by an unmapped guard page, so that running off its low end raises a data abort instead
+//! of overwriting whatever lies below. The physical pages backing a stack come from the kernel
+//! heap.
+
+use crate::{
+    memory::{
+        heap_alloc::kernel_heap_allocator,
+        mmu::{
+            self, AccessPermissions, AttributeFields, KernelGranule, MemAttributes, MemoryRegion,
+            PageAddress,
+        },
+        Address, Virtual,
+    },
+    synchronization::{interface::Mutex, IRQSafeLock, SpinLock},
+    warn,
+};
+use alloc::{
+    alloc::{GlobalAlloc, Layout},
+    vec::Vec,
+};
+use core::{
+    num::NonZeroUsize,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const STACK_ATTRIBUTES: AttributeFields = AttributeFields {
+    mem_attributes: MemAttributes::CacheableDRAM,
+    acc_perms: AccessPermissions::ReadWrite,
+    execute_never: true,
+};
+
+/// The virtual range handed out by the allocator.
+struct StackPool {
+    pool: Option<MemoryRegion<Virtual>>,
+
+    /// Released ranges, each including its guard page. Reused by stacks of the same size.
+    free: Vec<MemoryRegion<Virtual>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Stack size of threads that do not ask for a specific one.
+///
+/// Stacks are mapped in whole 64 KiB pages, so this is the smallest stack there is, up from the
+/// 8 KiB threads got before stacks had guard pages. The backing comes page-aligned out of the
+/// 16 MiB kernel heap, which therefore holds at most a few hundred stacks.
+pub const DEFAULT_STACK_SIZE: usize = KernelGranule::SIZE;
+
+/// A thread stack. Unmapped and freed again on drop.
+pub struct Stack {
+    /// The stack pages, without the guard page.
+    virt_region: MemoryRegion<Virtual>,
+
+    /// Heap allocation providing the physical pages.
+    backing: *mut u8,
+}
+
+/// Allocator for thread stacks.
+pub struct StackAllocator {
+    inner: IRQSafeLock<SpinLock<StackPool>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static KERNEL_STACK_ALLOCATOR: StackAllocator = StackAllocator::new();
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn backing_layout(num_pages: usize) -> Layout {
+    Layout::from_size_align(num_pages * KernelGranule::SIZE, KernelGranule::SIZE).unwrap()
+}
+
+impl StackPool {
+    /// Take a range of `num_pages` pages, preferring a released one.
+    fn alloc(&mut self, num_pages: NonZeroUsize) -> Result<MemoryRegion<Virtual>, &'static str> {
+        if let Some(pos) = self
+            .free
+            .iter()
+            .position(|r| r.num_pages() == num_pages.get())
+        {
+            return Ok(self.free.swap_remove(pos));
+        }
+
+        self.pool
+            .as_mut()
+            .ok_or("Allocator not initialized")?
+            .take_first_n_pages(num_pages)
+    }
+}
+
+impl StackAllocator {
+    const fn new() -> Self {
+        Self {
+            inner: IRQSafeLock::new(SpinLock::new(StackPool {
+                pool: None,
+                free: Vec::new(),
+            })),
+        }
+    }
+
+    fn with_pool<R>(&self, f: impl FnOnce(&mut StackPool) -> R) -> R {
+        self.inner.lock(|spin_lock| spin_lock.lock(f))
+    }
+
+    fn free(&self, stack: &Stack) {
+        let num_pages = stack.virt_region.num_pages();
+        let with_guard = MemoryRegion::new(
+            stack
+                .virt_region
+                .start_page_addr()
+                .checked_offset(-1)
+                .unwrap(),
+            stack.virt_region.end_exclusive_page_addr(),
+        );
+
+        self.with_pool(|pool| {
+            unsafe { mmu::kernel_unmap_at_runtime(&stack.virt_region) }
+                .expect("Unmapping a thread stack failed");
+            pool.free.push(with_guard);
+        });
+
+        unsafe { kernel_heap_allocator().dealloc(stack.backing, backing_layout(num_pages)) }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// The backing memory is owned by the stack.
+unsafe impl Send for Stack {}
+
+impl Stack {
+    /// Lowest address of the stack.
+    pub fn bottom(&self) -> Address<Virtual> {
+        self.virt_region.start_addr()
+    }
+
+    /// Initial stack pointer, one past the highest address.
+    pub fn top(&self) -> Address<Virtual> {
+        self.virt_region.end_exclusive_page_addr().into_inner()
+    }
+
+    pub fn size(&self) -> usize {
+        self.virt_region.size()
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        KERNEL_STACK_ALLOCATOR.free(self);
+    }
+}
+
+/// Return a reference to the kernel's thread stack allocator.
+pub fn kernel_stack_allocator() -> &'static StackAllocator {
+    &KERNEL_STACK_ALLOCATOR
+}
+
+impl StackAllocator {
+    /// Allocate a stack of at least `size` bytes, rounded up to whole pages.
+    pub fn alloc(&self, size: usize) -> Result<Stack, &'static str> {
+        let num_pages = size.div_ceil(KernelGranule::SIZE).max(1);
+
+        let backing = unsafe { kernel_heap_allocator().alloc(backing_layout(num_pages)) };
+        if backing.is_null() {
+            return Err("Out of heap memory for thread stack");
+        }
+
+        // The heap is mapped linearly, so the backing is physically contiguous as well.
+        let phys_start =
+            mmu::try_kernel_virt_page_addr_to_phys_page_addr(PageAddress::from(backing as usize))?;
+        let phys_region = MemoryRegion::new(
+            phys_start,
+            phys_start.checked_offset(num_pages as isize).unwrap(),
+        );
+
+        let mapped = self.with_pool(|pool| {
+            let with_guard = pool.alloc(NonZeroUsize::new(num_pages + 1).unwrap())?;
+
+            // The lowest page stays unmapped.
+            let virt_region = MemoryRegion::new(
+                with_guard.start_page_addr().checked_offset(1).unwrap(),
+                with_guard.end_exclusive_page_addr(),
+            );
+
+            match unsafe {
+                mmu::kernel_map_at_runtime(&virt_region, &phys_region, &STACK_ATTRIBUTES)
+            } {
+                Ok(()) => Ok(virt_region),
+                Err(x) => {
+                    pool.free.push(with_guard);
+                    Err(x)
+                }
+            }
+        });
+
+        match mapped {
+            Ok(virt_region) => Ok(Stack {
+                virt_region,
+                backing,
+            }),
+            Err(x) => {
+                unsafe { kernel_heap_allocator().dealloc(backing, backing_layout(num_pages)) };
+                Err(x)
+            }
+        }
+    }
+}
+
+/// Query the BSP for the thread stacks region and initialize the kernel's stack allocator with it.
+pub fn kernel_init_stack_allocator() {
+    static INIT_DONE: AtomicBool = AtomicBool::new(false);
+    if INIT_DONE.load(Ordering::Relaxed) {
+        warn!("Already initialized");
+        return;
+    }
+
+    let region = mmu::virt_thread_stacks_region();
+    KERNEL_STACK_ALLOCATOR.with_pool(|pool| pool.pool = Some(region));
+
+    INIT_DONE.store(true, Ordering::Relaxed);
+}
+
+/// Checks if the address lies in the guard page of the stack starting at `stack_bottom`.
+pub fn is_guard_page_addr(stack_bottom: Address<Virtual>, addr: Address<Virtual>) -> bool {
+    addr < stack_bottom && addr >= stack_bottom - KernelGranule::SIZE
+}
@@ -4,7 +4,7 @@
 
 //! A panic handler that infinitely waits.
 
-use crate::{backtrace, cpu, exception, println};
+use crate::{backtrace, cpu, exception, println, smp};
 use core::panic::PanicInfo;
 
 //--------------------------------------------------------------------------------------------------
@@ -55,6 +55,9 @@ fn panic(info: &PanicInfo) -> ! {
     // Protect against panic infinite loops if any of the following code panics itself.
     panic_prevent_reenter();
 
+    // Keep the other cores from printing over the panic message or making matters worse.
+    smp::stop_others();
+
     let timestamp = crate::time::time_manager().uptime();
     let (location, line, column) = match info.location() {
         Some(loc) => (loc.file(), loc.line(), loc.column()),
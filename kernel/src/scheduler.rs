@@ -1,5 +1,7 @@
 use core::fmt;
+use core::ops::{Deref, DerefMut};
 use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
 use core::{borrow::BorrowMut, cell::UnsafeCell};
 
 use alloc::collections::linked_list::Iter;
@@ -20,56 +22,148 @@ use alloc::{
 };
 use spin::{mutex::SpinMutex, rwlock::RwLock};
 
-use crate::cpu::core_id;
+use crate::cpu::{core_id, NR_CPUS};
+use crate::drivers::get_gic;
 use crate::exception::arch_exception::ExceptionContext;
-use crate::synchronization::IRQSafeLock;
+use crate::exception::asynchronous::irq_map;
+use crate::synchronization::{interface::ReadWriteEx, CoreLocalLock, IRQSafeLock, InitStateLock};
 use crate::time::time_manager;
-use crate::{info, random};
+use crate::{info, per_cpu, random, smp};
 use crate::{
-    synchronization::{interface::Mutex, SpinLock},
-    thread::Thread,
+    synchronization::{interface::Mutex, rcu, SpinLock},
+    thread::{stats::ThreadState, Affinity, Thread},
 };
 
-pub static CURRENT: [IRQSafeLock<Option<u64>>; 4] = [
-    IRQSafeLock::new(Option::None), //CORE0
-    IRQSafeLock::new(Option::None), //CORE1
-    IRQSafeLock::new(Option::None), //CORE2
-    IRQSafeLock::new(Option::None), //CORE3
-];
+pub mod balance;
+pub mod edf;
+pub mod mlfq;
+pub mod preempt;
+pub mod random_picker;
+pub mod round_robin;
+pub mod tick;
+pub mod watchdog;
 
-pub static RUNNING: [ThreadQueue; 4] = [
-    ThreadQueue::new(), //CORE0
-    ThreadQueue::new(), //CORE1
-    ThreadQueue::new(), //CORE2
-    ThreadQueue::new(), //CORE3
-];
+/// Scheduler interfaces.
+pub mod interface;
+
+static CUR_SCHEDULING_POLICY: InitStateLock<&'static (dyn interface::SchedulingPolicy + Sync)> =
+    InitStateLock::new(&round_robin::ROUND_ROBIN);
+
+per_cpu! {
+    /// PID of the thread running on each core.
+    ///
+    /// Stays locked across `__switch_to()`, hence a `CoreLocalLock`.
+    pub static CURRENT: CoreLocalLock<Option<u64>> = CoreLocalLock::new(None);
+
+    pub static RUNNING: ThreadQueue = ThreadQueue::new();
+
+    /// Real-time threads, scheduled earliest-deadline-first ahead of `RUNNING`.
+    pub static RT_RUNNING: ThreadQueue = ThreadQueue::new();
+
+    /// Threads that exited on each core, waiting to be freed by the next context switch on that
+    /// core.
+    static ZOMBIES: ThreadQueue = ThreadQueue::new();
+
+    /// Uptime in nanoseconds at which the current time slice of each core started.
+    static SLICE_START: AtomicU64 = AtomicU64::new(0);
+}
 
 pub static SLEEPING: ThreadQueue = ThreadQueue::new();
 
+/// Register a new scheduling policy.
+///
+/// Only allowed during kernel init, before any thread got scheduled.
+pub fn register_scheduling_policy(new_policy: &'static (dyn interface::SchedulingPolicy + Sync)) {
+    CUR_SCHEDULING_POLICY.write(|policy| *policy = new_policy);
+}
+
+/// Return a reference to the currently registered scheduling policy.
+pub fn scheduling_policy() -> &'static dyn interface::SchedulingPolicy {
+    CUR_SCHEDULING_POLICY.read(|policy| *policy)
+}
+
+/// Move the thread at `pos` to the back of `threads`.
+///
+/// Only relinks the list nodes, so no allocation happens and the moved thread keeps its address.
+pub fn move_to_back(threads: &mut LinkedList<Thread>, pos: usize) {
+    if let Some(node) = ThreadNode::split_from(threads, pos) {
+        node.append_to(threads);
+    }
+}
+
 pub struct ThreadQueue {
     irq_lock: IRQSafeLock<SpinLock<LinkedList<Thread>>>,
 }
 
+/// A thread that has been detached from its queue.
+///
+/// Wraps the single list node holding the thread. Moving a thread from one queue to another
+/// relinks that node instead of copying the thread, so the thread, and in particular its saved
+/// context, keeps its address. This allows a thread to be published in a new queue before the
+/// context switch away from it has stored its registers.
+pub struct ThreadNode(LinkedList<Thread>);
+
+impl ThreadNode {
+    /// Wrap a thread that is not part of any queue yet.
+    pub fn new(t: Thread) -> Self {
+        let mut list = LinkedList::new();
+        list.push_back(t);
+        Self(list)
+    }
+
+    /// Unwrap the thread, moving it out of its node.
+    pub fn into_inner(mut self) -> Thread {
+        self.0.pop_front().unwrap()
+    }
+
+    /// Append the node to `threads`.
+    pub fn append_to(mut self, threads: &mut LinkedList<Thread>) {
+        threads.append(&mut self.0);
+    }
+
+    /// Detach the thread at `pos` from `threads`.
+    pub fn split_from(threads: &mut LinkedList<Thread>, pos: usize) -> Option<Self> {
+        if pos >= threads.len() {
+            return None;
+        }
+
+        let mut tail = threads.split_off(pos);
+        let mut rest = tail.split_off(1);
+        threads.append(&mut rest);
+
+        Some(Self(tail))
+    }
+}
+
+impl Deref for ThreadNode {
+    type Target = Thread;
+
+    fn deref(&self) -> &Thread {
+        self.0.front().unwrap()
+    }
+}
+
+impl DerefMut for ThreadNode {
+    fn deref_mut(&mut self) -> &mut Thread {
+        self.0.front_mut().unwrap()
+    }
+}
+
 impl ThreadQueue {
-    const fn new() -> Self {
+    pub const fn new() -> Self {
         Self {
             irq_lock: IRQSafeLock::new(SpinLock::new(LinkedList::new())),
         }
     }
 
-    pub fn next(&self) -> Option<&mut Thread> {
-        self.irq_lock.lock(|spin_lock| {
-            spin_lock.lock(|threads| {
-                let len = threads.len();
-                let r = (random::next_u64() as usize) % len;
-                for (t, p) in threads.iter_mut().enumerate() {
-                    if t == r {
-                        return Some(p);
-                    }
-                }
-                Option::None
-            })
-        })
+    /// Run `f` on the thread to run next, as picked by the registered scheduling policy.
+    pub fn next<R>(&self, f: impl FnOnce(&mut Thread) -> R) -> Option<R> {
+        self.with_threads(|threads| scheduling_policy().pick_next(threads).map(f))
+    }
+
+    /// Grants the closure temporary access to the queued threads.
+    pub fn with_threads<R>(&self, f: impl FnOnce(&mut LinkedList<Thread>) -> R) -> R {
+        self.irq_lock.lock(|spin_lock| spin_lock.lock(f))
     }
 
     pub fn add(&self, t: Thread) {
@@ -80,6 +174,24 @@ impl ThreadQueue {
         })
     }
 
+    /// Detach the thread with the given PID, keeping it in its list node.
+    pub fn detach(&self, pid: u64) -> Option<ThreadNode> {
+        self.with_threads(|threads| {
+            let pos = threads.iter().position(|t| t.get_pid() == pid)?;
+            ThreadNode::split_from(threads, pos)
+        })
+    }
+
+    /// Detach the thread at the head of the queue.
+    pub fn detach_front(&self) -> Option<ThreadNode> {
+        self.with_threads(|threads| ThreadNode::split_from(threads, 0))
+    }
+
+    /// Append a detached thread to the queue.
+    pub fn attach(&self, node: ThreadNode) {
+        self.with_threads(|threads| node.append_to(threads))
+    }
+
     pub fn pop(&self) -> Thread {
         self.irq_lock
             .lock(|spin_lock| spin_lock.lock(|threads| threads.pop_front().unwrap()))
@@ -103,17 +215,9 @@ impl ThreadQueue {
         })
     }
 
-    pub fn get_by_pid(&self, pid: u64) -> Option<&mut Thread> {
-        self.irq_lock.lock(|spin_lock| {
-            spin_lock.lock(|threads| {
-                for p in threads.iter_mut() {
-                    if p.get_pid() == pid {
-                        return Some(p);
-                    }
-                }
-                Option::None
-            })
-        })
+    /// Run `f` on the thread with the given PID, if it is queued here.
+    pub fn with_pid<R>(&self, pid: u64, f: impl FnOnce(&mut Thread) -> R) -> Option<R> {
+        self.with_threads(|threads| threads.iter_mut().find(|t| t.get_pid() == pid).map(f))
     }
 
     pub fn clear(&self) {
@@ -146,29 +250,175 @@ fn store_context(s: &mut ExceptionContext, d: &mut ExceptionContext) {
     d.gpr = s.gpr;
     d.lr = s.lr;
     d.sp_el0 = s.sp_el0;
+    d.tpidr_el1 = s.tpidr_el1;
     d.spsr_el1 = s.spsr_el1;
 }
 
+/// Run `f` on a thread that is runnable on `core`, real-time or not.
+pub fn with_runnable<R>(core: usize, pid: u64, f: impl FnOnce(&mut Thread) -> R) -> Option<R> {
+    let mut f = Some(f);
+
+    RT_RUNNING[core]
+        .with_pid(pid, |t| (f.take().unwrap())(t))
+        .or_else(|| RUNNING[core].with_pid(pid, |t| (f.take().unwrap())(t)))
+}
+
+/// Take a thread off the run queues of `core`, real-time or not.
+pub fn remove_runnable(core: usize, pid: u64) -> Option<Thread> {
+    RT_RUNNING[core]
+        .remove(pid)
+        .or_else(|| RUNNING[core].remove(pid))
+}
+
+/// Detach a thread from the run queues of `core`, real-time or not.
+pub fn detach_runnable(core: usize, pid: u64) -> Option<ThreadNode> {
+    RT_RUNNING[core]
+        .detach(pid)
+        .or_else(|| RUNNING[core].detach(pid))
+}
+
+/// Put a detached thread back on the run queue of the core it belongs to.
+///
+/// If that core went offline meanwhile, the thread moves on to the least loaded online core.
+pub fn enqueue(mut node: ThreadNode) {
+    let mut core = node.core();
+    if smp::is_offline(core) {
+        core = least_loaded_core(node.affinity());
+        node.set_core(core);
+    }
+    node.stats().set_state(ThreadState::Runnable);
+
+    if node.is_realtime() {
+        RT_RUNNING[core].attach(node);
+    } else {
+        RUNNING[core].attach(node);
+    }
+
+    tick::enqueued(core);
+}
+
+/// Interrupt `core`, so that it makes a scheduling decision right away.
+pub fn kick(core: usize) {
+    unsafe { get_gic().send_sgi(irq_map::SGI_9, core as u8) }
+}
+
+/// Return the core in `allowed` with the fewest runnable threads, skipping offline cores.
+///
+/// Falls back to any core that is not offline if all of `allowed` are.
+pub fn least_loaded_core(allowed: Affinity) -> usize {
+    let load = |&core: &usize| RUNNING[core].size() + RT_RUNNING[core].size();
+
+    allowed
+        .cores()
+        .filter(|&core| !smp::is_offline(core))
+        .min_by_key(load)
+        .or_else(|| {
+            (0..NR_CPUS)
+                .filter(|&core| !smp::is_offline(core))
+                .min_by_key(load)
+        })
+        .expect("No core left online")
+}
+
+/// Whether `core` has nothing left but its idle thread, which is running.
+pub fn is_evacuated(core: usize) -> bool {
+    RT_RUNNING[core].size() == 0
+        && RUNNING[core].with_threads(|threads| {
+            threads.len() == 1 && threads.front().is_some_and(|t| t.is_idle())
+        })
+}
+
+/// Hand an exited thread over to be freed once `core` has switched away from it.
+pub fn bury(core: usize, node: ThreadNode) {
+    ZOMBIES[core].attach(node);
+}
+
+/// Pick the thread that runs next on `core`.
+///
+/// `current` is the thread whose time slice just ended, if any. Eligible real-time threads always
+/// win, earliest deadline first. Otherwise the registered scheduling policy picks from `RUNNING`.
+/// Before picking, threads may be handed over to idle or less loaded cores.
+///
+/// Returns the PID of the picked thread and its saved context, which stays in place as long as the
+/// thread does not run anywhere else.
+pub fn pick_next(core: usize, current: Option<u64>) -> (u64, *mut ExceptionContext) {
+    // Whatever exited on this core before is not running anymore, so its stack can go.
+    ZOMBIES[core].clear();
+    preempt::clear_deferred(core);
+    rcu::quiescent_state(core);
+
+    let now = time_manager().uptime();
+    let slice_start = SLICE_START[core].swap(now.as_nanos() as u64, Ordering::Relaxed);
+    let elapsed = now.saturating_sub(Duration::from_nanos(slice_start));
+
+    edf::update(core, current, elapsed, now);
+    balance::balance(core, current);
+
+    let switch_in = |next: &mut Thread| {
+        next.stats()
+            .switched_in(core, now, current == Some(next.get_pid()));
+        next.restore_fp();
+
+        let ctx: *mut ExceptionContext = next.get_ex_context();
+        (next.get_pid(), next.is_idle(), ctx)
+    };
+    let (pid, idle, ctx) = edf::pick(core, switch_in).unwrap_or_else(|| {
+        RUNNING[core]
+            .next(switch_in)
+            .expect("No next thread found!")
+    });
+    balance::set_idle(core, idle);
+    tick::scheduled(core, idle);
+    watchdog::scheduled(core, pid);
+
+    (pid, ctx)
+}
+
+/// Preempt the thread interrupted by the tick or SGI_9, replacing the context in `_ec` with the one
+/// of the next thread.
+///
+/// Held back until preemption is enabled again if the thread disabled it.
 pub fn reschedule_from_context(_ec: &mut ExceptionContext) {
     let core: usize = core_id();
+    watchdog::touch_irq(core);
+    if preempt::is_disabled(core) {
+        preempt::defer(core);
+        return;
+    }
+
     CURRENT[core].lock(|cur_pid| {
         if cur_pid.is_some() {
-            let _cur_thread = RUNNING[core]
-                .get_by_pid(cur_pid.unwrap())
-                .unwrap_or_else(|| {
-                    panic!(
-                        "[IRQ] Cannot find PID={} in RUNNING[{}]",
-                        cur_pid.unwrap(),
-                        core
-                    )
-                });
-            store_context(_ec, _cur_thread.get_ex_context());
+            with_runnable(core, cur_pid.unwrap(), |_cur_thread| {
+                store_context(_ec, _cur_thread.get_ex_context());
+                if !_cur_thread.is_realtime() {
+                    scheduling_policy().thread_preempted(_cur_thread);
+                }
+                _cur_thread.stats().switched_out(
+                    time_manager().uptime(),
+                    ThreadState::Runnable,
+                    false,
+                );
+                _cur_thread.save_fp();
+            })
+            .unwrap_or_else(|| {
+                panic!(
+                    "[IRQ] Cannot find PID={} in RUNNING[{}]",
+                    cur_pid.unwrap(),
+                    core
+                )
+            });
         } else {
             info!("Current = None");
         }
 
-        let next_thread: &mut Thread = RUNNING[core].next().expect("No next thread found!");
-        *cur_pid = Some(next_thread.get_pid());
-        store_context(next_thread.get_ex_context(), _ec);
+        // The preempted thread's context is saved now, so it may leave this core as well.
+        balance::migrate_pending(core);
+        if smp::is_offline(core) {
+            balance::evacuate(core);
+        }
+
+        let (next_pid, next_ctx) = pick_next(core, *cur_pid);
+        *cur_pid = Some(next_pid);
+        store_context(unsafe { &mut *next_ctx }, _ec);
     })
 }
@@ -0,0 +1,174 @@
+//! Load balancing between the per-core run queues.
+//!
+//! Only the core owning a run queue moves threads off it, from within `pick_next()`. At that point
+//! every thread in the queue except the one that ran last has its context saved, so it can be
+//! handed over safely. For the same reason an idle core cannot take threads off another core's
+//! queue itself. It announces itself in `IDLE_CORES` instead, and the next core that schedules
+//! with a thread to spare hands it over right away.
+//!
+//! Explicit migrations requested through `thread::migrate()` are carried out the same way, by the
+//! owning core in `migrate_pending()`.
+//!
+//! Idle threads are pinned to their core and real-time threads stay on the core that admitted them.
+//! Other threads only ever move to cores in their affinity mask, unless a core goes offline and none
+//! of them is left online.
+
+use super::{enqueue, least_loaded_core, RT_RUNNING, RUNNING};
+use crate::{debug, per_cpu, scheduler::ThreadNode, smp, thread::Thread};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Scheduling decisions of a core between two periodic balancing runs.
+const BALANCE_INTERVAL: usize = 20;
+
+/// Bit `n` is set while core `n` has nothing to run but its idle thread.
+static IDLE_CORES: AtomicUsize = AtomicUsize::new(0);
+
+per_cpu! {
+    /// Scheduling decisions since the last periodic balancing run of each core.
+    static PICKS: AtomicUsize = AtomicUsize::new(0);
+}
+
+static MIGRATIONS: AtomicU64 = AtomicU64::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn load(core: usize) -> usize {
+    RUNNING[core].size() + RT_RUNNING[core].size()
+}
+
+fn is_migratable(t: &Thread, current: Option<u64>, target: usize) -> bool {
+    !t.is_idle()
+        && !t.is_realtime()
+        && !t.has_pending_migration()
+        && t.affinity().contains(target)
+        && Some(t.get_pid()) != current
+}
+
+fn move_to(core: usize, target: usize, mut node: ThreadNode) {
+    debug!(
+        "[BALANCE] Moving PID={} from Core{} to Core{}",
+        node.get_pid(),
+        core,
+        target
+    );
+    node.set_core(target);
+    enqueue(node);
+    MIGRATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Hand one thread of `core` over to `target`. Returns false if there was none to spare.
+fn migrate_one(core: usize, target: usize, current: Option<u64>) -> bool {
+    let node = RUNNING[core].with_threads(|threads| {
+        let pos = threads
+            .iter()
+            .rposition(|t| is_migratable(t, current, target))?;
+        ThreadNode::split_from(threads, pos)
+    });
+
+    match node {
+        Some(node) => {
+            move_to(core, target, node);
+            true
+        }
+        None => false,
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Move a thread away from `core` if another core is idle or noticeably less loaded.
+///
+/// Must only be called by `core` itself while scheduling. `current` is the thread that ran last.
+pub fn balance(core: usize, current: Option<u64>) {
+    let idle = IDLE_CORES.load(Ordering::Relaxed) & !(1 << core);
+    if idle != 0 {
+        let target = idle.trailing_zeros() as usize;
+
+        // Claim the idle core, so that it is not flooded by every other core at once.
+        if IDLE_CORES.fetch_and(!(1 << target), Ordering::Relaxed) & (1 << target) != 0
+            && !migrate_one(core, target, current)
+        {
+            IDLE_CORES.fetch_or(1 << target, Ordering::Relaxed);
+        }
+        return;
+    }
+
+    if PICKS[core].fetch_add(1, Ordering::Relaxed) + 1 < BALANCE_INTERVAL {
+        return;
+    }
+    PICKS[core].store(0, Ordering::Relaxed);
+
+    let (target, target_load) = (0..RUNNING.len())
+        .filter(|&c| !smp::is_offline(c))
+        .map(|c| (c, load(c)))
+        .min_by_key(|&(_, l)| l)
+        .unwrap();
+
+    // A difference of one thread cannot be evened out by moving one.
+    if load(core) >= target_load + 2 {
+        migrate_one(core, target, current);
+    }
+}
+
+/// Hand over all threads of `core` that were asked to move by `thread::migrate()`.
+///
+/// Must only be called by `core` itself while scheduling, with the context of every thread in its
+/// run queue saved.
+pub fn migrate_pending(core: usize) {
+    loop {
+        let request = RUNNING[core].with_threads(|threads| {
+            let pos = threads.iter().position(|t| t.has_pending_migration())?;
+            let mut node = ThreadNode::split_from(threads, pos).unwrap();
+            let target = node.take_migration().unwrap();
+
+            Some((node, target))
+        });
+
+        match request {
+            // The affinity might have changed since the request was made.
+            Some((node, target)) if !node.affinity().contains(target) => {
+                enqueue(node);
+            }
+            Some((node, target)) => move_to(core, target, node),
+            None => break,
+        }
+    }
+}
+
+/// Hand over every thread of `core` except its idle thread, because `core` goes offline.
+///
+/// Must only be called by `core` itself while scheduling, with the context of every thread in its
+/// run queue saved.
+pub fn evacuate(core: usize) {
+    while let Some(node) = RUNNING[core].with_threads(|threads| {
+        let pos = threads.iter().position(|t| !t.is_idle())?;
+        ThreadNode::split_from(threads, pos)
+    }) {
+        let target = least_loaded_core(node.affinity());
+        move_to(core, target, node);
+    }
+}
+
+/// Record whether `core` is about to run its idle thread.
+///
+/// Cores going offline never count as idle, so that no thread is handed to them.
+pub fn set_idle(core: usize, idle: bool) {
+    if idle && !smp::is_offline(core) {
+        IDLE_CORES.fetch_or(1 << core, Ordering::Relaxed);
+    } else {
+        IDLE_CORES.fetch_and(!(1 << core), Ordering::Relaxed);
+    }
+}
+
+/// Number of threads moved between cores so far.
+pub fn migrations() -> u64 {
+    MIGRATIONS.load(Ordering::Relaxed)
+}
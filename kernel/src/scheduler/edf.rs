@@ -0,0 +1,311 @@
+//! Earliest-deadline-first real-time scheduling class.
+//!
+//! Real-time threads live in `RT_RUNNING[core]`, next to the normal `RUNNING` queues. Each one
+//! declares a period, a budget of CPU time per period and a deadline relative to the start of the
+//! period. Whenever a real-time thread is eligible, i.e. its current job is neither finished nor
+//! out of budget, it takes precedence over all normal threads. Among eligible threads, the one with
+//! the earliest absolute deadline runs.
+
+use super::{tick, RT_RUNNING};
+use crate::{
+    per_cpu, smp,
+    thread::{stats, Thread},
+    time::time_manager,
+    warn,
+};
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A full core, in parts per million.
+const PPM: u64 = 1_000_000;
+
+per_cpu! {
+    /// Summed density of the real-time threads admitted to each core, in parts per million.
+    ///
+    /// Counts threads until they exit, including the ones that sleep or wait in a queue.
+    static ADMITTED_PPM: AtomicU64 = AtomicU64::new(0);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Timing requirements of a periodic real-time thread.
+#[derive(Copy, Clone, Debug)]
+pub struct RealTimeParams {
+    /// Time between two job releases.
+    pub period: Duration,
+
+    /// CPU time a single job may consume.
+    pub budget: Duration,
+
+    /// Deadline of a job, relative to its release. Must not exceed the period.
+    pub deadline: Duration,
+}
+
+/// Runtime bookkeeping of a real-time thread.
+pub struct RealTimeState {
+    params: RealTimeParams,
+
+    /// Release time of the current job.
+    release: Duration,
+
+    /// CPU time consumed by the current job.
+    used: Duration,
+
+    /// The current job signalled completion through `thread::wait_next_period()`.
+    job_done: bool,
+
+    /// The current job used up its budget and may not run before the next release.
+    throttled: bool,
+
+    /// The deadline miss of the current job has already been reported.
+    miss_reported: bool,
+
+    overruns: u64,
+    deadline_misses: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl RealTimeParams {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.budget.is_zero() {
+            return Err("Real-time budget must not be zero");
+        }
+
+        if self.budget > self.deadline || self.deadline > self.period {
+            return Err("Real-time parameters must satisfy budget <= deadline <= period");
+        }
+
+        Ok(())
+    }
+
+    /// Share of a core the thread can claim, in parts per million.
+    ///
+    /// Uses budget / deadline instead of budget / period, which keeps the admission test
+    /// sufficient for deadlines shorter than the period.
+    fn density_ppm(&self) -> u64 {
+        (self.budget.as_nanos() * PPM as u128).div_ceil(self.deadline.as_nanos()) as u64
+    }
+}
+
+impl RealTimeState {
+    fn new(params: RealTimeParams, now: Duration) -> Self {
+        Self {
+            params,
+            release: now,
+            used: Duration::ZERO,
+            job_done: false,
+            throttled: false,
+            miss_reported: false,
+            overruns: 0,
+            deadline_misses: 0,
+        }
+    }
+
+    /// Charge CPU time to the current job and throttle it if it exceeded its budget.
+    fn charge(&mut self, pid: u64, elapsed: Duration) {
+        self.used += elapsed;
+
+        if !self.throttled && self.used > self.params.budget {
+            self.throttled = true;
+            self.overruns += 1;
+            warn!(
+                "[RT] PID={} overran its budget: {:?} > {:?} (overruns: {})",
+                pid, self.used, self.params.budget, self.overruns
+            );
+        }
+    }
+
+    /// Report deadline misses and release new jobs whose period started.
+    fn update(&mut self, pid: u64, now: Duration) {
+        if !self.job_done && !self.miss_reported && now > self.abs_deadline() {
+            self.miss_reported = true;
+            self.deadline_misses += 1;
+            warn!(
+                "[RT] PID={} missed its deadline by {:?} (misses: {})",
+                pid,
+                now - self.abs_deadline(),
+                self.deadline_misses
+            );
+        }
+
+        if now < self.release + self.params.period {
+            return;
+        }
+
+        // Skip all periods that passed in the meantime, so a late thread does not get a burst of
+        // back-to-back jobs.
+        while self.release + self.params.period <= now {
+            self.release += self.params.period;
+        }
+        self.used = Duration::ZERO;
+        self.job_done = false;
+        self.throttled = false;
+        self.miss_reported = false;
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl RealTimeParams {
+    /// Create an instance.
+    pub const fn new(period: Duration, budget: Duration, deadline: Duration) -> Self {
+        Self {
+            period,
+            budget,
+            deadline,
+        }
+    }
+}
+
+impl RealTimeState {
+    pub fn params(&self) -> &RealTimeParams {
+        &self.params
+    }
+
+    /// Absolute deadline of the current job.
+    pub fn abs_deadline(&self) -> Duration {
+        self.release + self.params.deadline
+    }
+
+    /// Whether the current job may run.
+    pub fn is_eligible(&self) -> bool {
+        !self.job_done && !self.throttled
+    }
+
+    /// Mark the current job as finished.
+    pub fn complete_job(&mut self) {
+        self.job_done = true;
+    }
+
+    /// Number of jobs that exceeded their budget.
+    pub fn overruns(&self) -> u64 {
+        self.overruns
+    }
+
+    /// Number of jobs that did not finish before their deadline.
+    pub fn deadline_misses(&self) -> u64 {
+        self.deadline_misses
+    }
+}
+
+/// Admit `thread` to the real-time class of `core` and return its PID.
+///
+/// The thread is rejected if the summed density of all real-time threads admitted to `core` would
+/// exceed 100%, since EDF could then no longer guarantee every deadline.
+pub fn admit(core: usize, mut thread: Thread, params: RealTimeParams) -> Result<u64, &'static str> {
+    params.validate()?;
+
+    let density = params.density_ppm();
+    ADMITTED_PPM[core]
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |load| {
+            (load + density <= PPM).then_some(load + density)
+        })
+        .map_err(|_| "Real-time admission control: task set not schedulable")?;
+
+    // Checked after claiming the share, while `smp::offline_core()` checks for claimed shares after
+    // marking the core offline. At least one of both sees the other.
+    if smp::is_offline(core) {
+        ADMITTED_PPM[core].fetch_sub(density, Ordering::SeqCst);
+        return Err("Core is offline");
+    }
+
+    thread.set_core(core);
+    thread.set_realtime(RealTimeState::new(params, time_manager().uptime()));
+    let pid = thread.get_pid();
+    stats::register(thread.name(), thread.stats().clone());
+    RT_RUNNING[core].add(thread);
+    tick::enqueued(core);
+
+    Ok(pid)
+}
+
+/// Whether real-time threads are admitted to `core`, whether they are runnable right now or not.
+pub fn has_admitted(core: usize) -> bool {
+    ADMITTED_PPM[core].load(Ordering::SeqCst) != 0
+}
+
+/// Give the share of its core back that `thread` claimed when it was admitted, because it exits.
+///
+/// Does nothing for normal threads.
+pub fn release(thread: &Thread) {
+    if let Some(rt) = thread.realtime() {
+        ADMITTED_PPM[thread.core()].fetch_sub(rt.params().density_ppm(), Ordering::SeqCst);
+    }
+}
+
+/// Charge the slice that just ended to `current` and advance the periods of all real-time threads
+/// of `core`.
+pub fn update(core: usize, current: Option<u64>, elapsed: Duration, now: Duration) {
+    RT_RUNNING[core].with_threads(|threads| {
+        for t in threads.iter_mut() {
+            let pid = t.get_pid();
+            if let Some(rt) = t.realtime_mut() {
+                if current == Some(pid) {
+                    rt.charge(pid, elapsed);
+                }
+                rt.update(pid, now);
+            }
+        }
+    })
+}
+
+/// Run `f` on the eligible real-time thread of `core` with the earliest deadline, if there is one.
+pub fn pick<R>(core: usize, f: impl FnOnce(&mut Thread) -> R) -> Option<R> {
+    RT_RUNNING[core].with_threads(|threads| {
+        threads
+            .iter_mut()
+            .filter(|t| t.realtime().map_or(false, |rt| rt.is_eligible()))
+            .min_by_key(|t| t.realtime().unwrap().abs_deadline())
+            .map(f)
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    fn params(budget_ms: u64, deadline_ms: u64, period_ms: u64) -> RealTimeParams {
+        RealTimeParams {
+            period: Duration::from_millis(period_ms),
+            budget: Duration::from_millis(budget_ms),
+            deadline: Duration::from_millis(deadline_ms),
+        }
+    }
+
+    /// Check that only budget <= deadline <= period with a non-zero budget is accepted.
+    #[kernel_test]
+    fn validate_requires_ordered_parameters() {
+        assert!(params(2, 5, 10).validate().is_ok());
+        assert!(params(10, 10, 10).validate().is_ok());
+
+        assert!(params(0, 5, 10).validate().is_err());
+        assert!(params(6, 5, 10).validate().is_err());
+        assert!(params(2, 11, 10).validate().is_err());
+    }
+
+    /// Check that the density is budget / deadline, rounded up.
+    #[kernel_test]
+    fn density_uses_deadline_and_rounds_up() {
+        assert_eq!(params(2, 5, 10).density_ppm(), 400_000);
+        assert_eq!(params(10, 10, 10).density_ppm(), PPM);
+        assert_eq!(params(1, 3, 3).density_ppm(), 333_334);
+    }
+}
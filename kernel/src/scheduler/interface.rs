@@ -0,0 +1,25 @@
+use crate::thread::Thread;
+use alloc::collections::LinkedList;
+
+/// A scheduling policy decides which thread of a per-core run queue gets the CPU next.
+///
+/// Every context switch, whether preempting, yielding, blocking in `thread::block_on_if` or
+/// exiting, picks the next thread through `scheduler::pick_next`. Unless a real-time thread is due,
+/// that goes through the currently registered policy, so policies can be swapped without touching
+/// the context switch code.
+pub trait SchedulingPolicy {
+    /// Human readable name of the policy.
+    fn name(&self) -> &'static str;
+
+    /// Pick the thread that runs next.
+    ///
+    /// The policy is allowed to reorder `threads`, but must not add or remove entries. Returns
+    /// `None` only if `threads` is empty.
+    fn pick_next<'a>(&self, threads: &'a mut LinkedList<Thread>) -> Option<&'a mut Thread>;
+
+    /// Called when a thread is taken off the CPU by the scheduler tick.
+    fn thread_preempted(&self, _thread: &mut Thread) {}
+
+    /// Called when a thread gives up the CPU on its own, by yielding or blocking.
+    fn thread_yielded(&self, _thread: &mut Thread) {}
+}
@@ -0,0 +1,102 @@
+//! Multi-level feedback queue scheduling policy.
+
+use super::{interface, move_to_back};
+use crate::{
+    cpu::{core_id, per_cpu::PerCpu, NR_CPUS},
+    thread::Thread,
+};
+use alloc::collections::LinkedList;
+use core::{
+    cmp::Reverse,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of consecutive preemptions after which a thread drops one level.
+const SLICES_PER_LEVEL: u8 = 2;
+
+/// Number of picks on a core after which all its threads are reset to their static priority.
+/// Prevents starvation of threads that got demoted all the way down.
+const BOOST_INTERVAL: usize = 200;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Multi-level feedback queue.
+///
+/// Each thread starts at the level given by its static priority. Threads that keep getting
+/// preempted (CPU hogs) are demoted one level at a time, threads that yield or block are promoted
+/// back up, never above their static priority. The highest level wins, threads on the same level
+/// are run round-robin. The idle thread is only picked if no other thread is runnable.
+pub struct Mlfq {
+    picks: PerCpu<AtomicUsize>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+pub static MLFQ: Mlfq = Mlfq::new();
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Mlfq {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            picks: PerCpu::new([const { AtomicUsize::new(0) }; NR_CPUS]),
+        }
+    }
+}
+
+impl interface::SchedulingPolicy for Mlfq {
+    fn name(&self) -> &'static str {
+        "Multi-Level Feedback Queue"
+    }
+
+    fn pick_next<'a>(&self, threads: &'a mut LinkedList<Thread>) -> Option<&'a mut Thread> {
+        if threads.is_empty() {
+            return None;
+        }
+
+        let core: usize = core_id();
+        if self.picks[core].fetch_add(1, Ordering::Relaxed) % BOOST_INTERVAL == BOOST_INTERVAL - 1 {
+            for t in threads.iter_mut() {
+                t.set_level(t.priority().get());
+            }
+        }
+
+        // Highest level first, and the one closest to the head of the queue among equals.
+        let pos = threads
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| !t.is_idle())
+            .max_by_key(|(i, t)| (t.level(), Reverse(*i)))
+            .map(|(i, _)| i)
+            .unwrap_or(0);
+        move_to_back(threads, pos);
+
+        threads.back_mut()
+    }
+
+    fn thread_preempted(&self, thread: &mut Thread) {
+        if thread.charge_slice() >= SLICES_PER_LEVEL && thread.level() > 0 {
+            thread.set_level(thread.level() - 1);
+        }
+    }
+
+    fn thread_yielded(&self, thread: &mut Thread) {
+        let level = thread.level();
+        if level < thread.priority().get() {
+            thread.set_level(level + 1);
+        } else {
+            thread.set_level(level);
+        }
+    }
+}
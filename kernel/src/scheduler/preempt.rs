@@ -0,0 +1,95 @@
+//! Preemption control.
+//!
+//! Each core counts how often preemption has been disabled on it. While the count is non-zero, the
+//! tick and SGI_9 do not switch away from the running thread. They only note that a switch is due,
+//! and the final `preempt_enable()` carries it out. Unlike masking IRQs, this keeps the UART, the
+//! timer and all other interrupts going.
+//!
+//! A thread must not block or yield with preemption disabled, since the count belongs to the core
+//! and not to the thread.
+
+use crate::{
+    cpu::core_id,
+    exception::asynchronous::{local_irq_mask_save, local_irq_restore},
+    per_cpu, thread,
+};
+use aarch64_cpu::registers::SPSel;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tock_registers::interfaces::Readable;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+per_cpu! {
+    static PREEMPT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    /// Set when a switch was held back on the core because preemption was disabled.
+    static NEED_RESCHED: AtomicBool = AtomicBool::new(false);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Threads run on SP_EL0, exception handlers on SP_EL1.
+fn in_thread_context() -> bool {
+    SPSel.read(SPSel::SP) == 0
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Keep the running thread on the executing core until the matching `preempt_enable()`.
+///
+/// Calls nest.
+pub fn preempt_disable() {
+    // Masked, so that the thread cannot move to another core between reading the core ID and
+    // raising the count.
+    let saved = local_irq_mask_save();
+    PREEMPT_COUNT[core_id::<usize>()].fetch_add(1, Ordering::Relaxed);
+    local_irq_restore(saved);
+}
+
+/// Undo one `preempt_disable()`.
+///
+/// The last call switches to another thread right away if a switch was held back meanwhile.
+pub fn preempt_enable() {
+    let saved = local_irq_mask_save();
+    let core: usize = core_id();
+    let previous = PREEMPT_COUNT[core].fetch_sub(1, Ordering::Relaxed);
+    assert!(previous > 0, "preempt_enable() without preempt_disable()");
+
+    let switch =
+        previous == 1 && in_thread_context() && NEED_RESCHED[core].swap(false, Ordering::Relaxed);
+    local_irq_restore(saved);
+
+    if switch {
+        thread::preempt();
+    }
+}
+
+/// Run `f` with preemption disabled.
+pub fn without_preemption<R>(f: impl FnOnce() -> R) -> R {
+    preempt_disable();
+    let ret = f();
+    preempt_enable();
+
+    ret
+}
+
+/// Whether preemption is disabled on `core`.
+pub fn is_disabled(core: usize) -> bool {
+    PREEMPT_COUNT[core].load(Ordering::Relaxed) != 0
+}
+
+/// Note that `core` has to switch threads as soon as preemption is enabled again.
+pub fn defer(core: usize) {
+    NEED_RESCHED[core].store(true, Ordering::Relaxed);
+}
+
+/// Drop a held back switch of `core`, because it is being made right now.
+pub fn clear_deferred(core: usize) {
+    NEED_RESCHED[core].store(false, Ordering::Relaxed);
+}
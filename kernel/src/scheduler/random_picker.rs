@@ -0,0 +1,39 @@
+//! Random scheduling policy.
+
+use super::interface;
+use crate::{random, thread::Thread};
+use alloc::collections::LinkedList;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Picks a uniformly random thread of the queue, idle thread included.
+///
+/// Neither fair nor reproducible. Kept around to compare against the other policies.
+pub struct RandomPicker;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+pub static RANDOM_PICKER: RandomPicker = RandomPicker {};
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl interface::SchedulingPolicy for RandomPicker {
+    fn name(&self) -> &'static str {
+        "Random"
+    }
+
+    fn pick_next<'a>(&self, threads: &'a mut LinkedList<Thread>) -> Option<&'a mut Thread> {
+        if threads.is_empty() {
+            return None;
+        }
+
+        let r = (random::next_u64() as usize) % threads.len();
+        threads.iter_mut().nth(r)
+    }
+}
@@ -0,0 +1,43 @@
+//! Deterministic round-robin scheduling policy.
+
+use super::{interface, move_to_back};
+use crate::thread::Thread;
+use alloc::collections::LinkedList;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Runs the threads of a queue in FIFO order, one time slice each.
+///
+/// The idle thread is only picked if no other thread is runnable.
+pub struct RoundRobin;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+pub static ROUND_ROBIN: RoundRobin = RoundRobin {};
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl interface::SchedulingPolicy for RoundRobin {
+    fn name(&self) -> &'static str {
+        "Round-Robin"
+    }
+
+    fn pick_next<'a>(&self, threads: &'a mut LinkedList<Thread>) -> Option<&'a mut Thread> {
+        if threads.is_empty() {
+            return None;
+        }
+
+        // The head of the queue is the thread that has been waiting the longest. Picked threads
+        // go to the back of the queue.
+        let pos = threads.iter().position(|t| !t.is_idle()).unwrap_or(0);
+        move_to_back(threads, pos);
+
+        threads.back_mut()
+    }
+}
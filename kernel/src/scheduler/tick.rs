@@ -0,0 +1,110 @@
+//! Dynamic scheduler tick.
+//!
+//! The preemption tick is only needed on cores that have something to preempt: more than one
+//! normal thread besides the idle thread, or any real-time thread, whose budgets and periods are
+//! enforced on ticks. Every other core runs tickless. A core running only its idle thread sleeps in
+//! WFI until an interrupt arrives, either the timer programmed for the next real timeout or an
+//! SGI_9 from a core that handed it a thread.
+//!
+//! Every ticking core has a one-shot tick timeout pinned to its own timer, which re-arms itself as
+//! long as the core still needs it.
+
+use super::{kick, reschedule_from_context, RT_RUNNING, RUNNING};
+use crate::{
+    exception::{arch_exception::ExceptionContext, asynchronous::is_local_irq_masked},
+    per_cpu,
+    time::time_manager,
+};
+use alloc::boxed::Box;
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Length of a time slice.
+const TICK: Duration = Duration::from_millis(5);
+
+/// Bit `n` is set while core `n` needs the tick.
+static TICKING: AtomicUsize = AtomicUsize::new(0);
+
+/// Bit `n` is set while core `n` runs its idle thread.
+static IDLING: AtomicUsize = AtomicUsize::new(0);
+
+per_cpu! {
+    /// Whether the tick timeout of each core is pending.
+    static ARMED: AtomicBool = AtomicBool::new(false);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn needs_tick(core: usize) -> bool {
+    RT_RUNNING[core].size() > 0 || RUNNING[core].size() > 2
+}
+
+/// Arm the tick timeout of `core` if it needs the tick and the timeout is not pending yet.
+fn arm(core: usize) {
+    if TICKING.load(Ordering::SeqCst) & (1 << core) != 0
+        && !ARMED[core].swap(true, Ordering::SeqCst)
+    {
+        time_manager().set_timeout_once_on(core, TICK, Box::new(move |ec| tick(core, ec)));
+    }
+}
+
+/// Recompute whether `core` needs the tick.
+fn refresh(core: usize) {
+    if needs_tick(core) {
+        TICKING.fetch_or(1 << core, Ordering::SeqCst);
+        arm(core);
+    } else {
+        TICKING.fetch_and(!(1 << core), Ordering::SeqCst);
+    }
+}
+
+fn tick(core: usize, ec: &mut ExceptionContext) {
+    // Cleared before looking at `TICKING`, so that another core handing this one a thread
+    // meanwhile arms the timeout itself.
+    ARMED[core].store(false, Ordering::SeqCst);
+    arm(core);
+
+    if TICKING.load(Ordering::SeqCst) & (1 << core) != 0 {
+        reschedule_from_context(ec);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Record the scheduling decision `core` just made.
+///
+/// Must only be called by `core` itself, with IRQs masked. `idle` tells whether it is about to run
+/// its idle thread.
+pub fn scheduled(core: usize, idle: bool) {
+    // Arming the tick must not re-enter the scheduler through `preempt_enable()`.
+    debug_assert!(is_local_irq_masked());
+
+    if idle {
+        IDLING.fetch_or(1 << core, Ordering::SeqCst);
+    } else {
+        IDLING.fetch_and(!(1 << core), Ordering::SeqCst);
+    }
+
+    refresh(core);
+}
+
+/// Record that a thread was added to the run queues of `core`.
+///
+/// A core idling without a tick is interrupted, so that it picks the thread up right away.
+pub fn enqueued(core: usize) {
+    refresh(core);
+
+    if IDLING.load(Ordering::SeqCst) & (1 << core) != 0 {
+        kick(core);
+    }
+}
@@ -0,0 +1,211 @@
+//! Soft- and hard-lockup watchdog.
+//!
+//! Every core keeps two heartbeats. One is touched on each scheduling decision, the other whenever
+//! the core is interrupted to reschedule, even if preemption is disabled and the decision is held
+//! back. A periodic check pinned to each core interrupts the core itself, so that tickless and idle
+//! cores touch their heartbeats as well, and looks for heartbeats of the other cores that went
+//! stale:
+//!
+//! - Soft lockup: the core takes IRQs, but did not schedule for `THRESHOLD`. Usually a thread
+//!   spinning with preemption disabled.
+//! - Hard lockup: the core did not take an IRQ for `THRESHOLD`. Usually a thread spinning with IRQs
+//!   masked, e.g. while waiting for an `IRQSafeLock`.
+//!
+//! A stuck core is asked with `SGI_11` to print its interrupted context, the PID in `CURRENT` and a
+//! backtrace. A core with IRQs masked cannot answer, so only the PID it last scheduled is reported
+//! for it. Afterwards the kernel either panics or continues, see `set_panic_on_lockup()`.
+
+use super::CURRENT;
+use crate::{
+    backtrace::ContextBacktrace,
+    cpu::core_id,
+    drivers::get_gic,
+    exception::{arch_exception::ExceptionContext, asynchronous::irq_map},
+    per_cpu, smp,
+    synchronization::interface::Mutex,
+    time::time_manager,
+    warn,
+};
+use alloc::boxed::Box;
+use core::{
+    hint,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How often the heartbeats are checked.
+const PERIOD: Duration = Duration::from_millis(500);
+
+/// How long a heartbeat may stay untouched before its core counts as stuck.
+const THRESHOLD: Duration = Duration::from_secs(2);
+
+/// How long to wait for a stuck core to print its state.
+const DUMP_TIMEOUT: Duration = Duration::from_millis(100);
+
+per_cpu! {
+    /// Uptime in nanoseconds of each core's last scheduling decision.
+    static HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+
+    /// Uptime in nanoseconds at which each core was last interrupted to reschedule.
+    static IRQ_HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+
+    /// PID each core scheduled last.
+    static LAST_PID: AtomicU64 = AtomicU64::new(0);
+
+    /// Set once the current stall of each core has been reported.
+    static REPORTED: AtomicBool = AtomicBool::new(false);
+
+    /// Set by each core once it printed its state in response to `SGI_11`.
+    static DUMPED: AtomicBool = AtomicBool::new(false);
+}
+
+/// Whether a lockup brings the kernel down.
+static PANIC_ON_LOCKUP: AtomicBool = AtomicBool::new(false);
+
+#[derive(Copy, Clone)]
+enum Lockup {
+    Soft,
+    Hard,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn now_nanos() -> u64 {
+    time_manager().uptime().as_nanos() as u64
+}
+
+fn stale(heartbeat: &AtomicU64, now: u64) -> bool {
+    now.saturating_sub(heartbeat.load(Ordering::Relaxed)) > THRESHOLD.as_nanos() as u64
+}
+
+/// Have the stuck `core` print its state. Returns whether it did in time.
+fn request_dump(core: usize) -> bool {
+    DUMPED[core].store(false, Ordering::SeqCst);
+    unsafe { get_gic().send_sgi(irq_map::SGI_11, core as u8) }
+
+    let deadline = time_manager().uptime() + DUMP_TIMEOUT;
+    while !DUMPED[core].load(Ordering::SeqCst) {
+        if time_manager().uptime() >= deadline {
+            return false;
+        }
+        hint::spin_loop();
+    }
+
+    true
+}
+
+fn report(core: usize, lockup: Lockup, now: u64) {
+    let (kind, heartbeat) = match lockup {
+        Lockup::Soft => ("Soft", &HEARTBEAT[core]),
+        Lockup::Hard => ("Hard", &IRQ_HEARTBEAT[core]),
+    };
+    let stuck_for = Duration::from_nanos(now.saturating_sub(heartbeat.load(Ordering::Relaxed)));
+
+    warn!(
+        "[WATCHDOG] {} lockup on Core{}, stuck for {:?}",
+        kind, core, stuck_for
+    );
+
+    if !request_dump(core) {
+        warn!(
+            "[WATCHDOG] Core{} does not respond to IRQs, last scheduled PID={}",
+            core,
+            LAST_PID[core].load(Ordering::Relaxed)
+        );
+    }
+
+    if PANIC_ON_LOCKUP.load(Ordering::Relaxed) {
+        panic!("[WATCHDOG] {} lockup on Core{}", kind, core);
+    }
+}
+
+/// Look for other cores that are stuck, then interrupt the executing core so that it touches its
+/// heartbeats.
+fn check(_ec: &mut ExceptionContext) {
+    let me: usize = core_id();
+    let now = now_nanos();
+
+    // Running this shows that the executing core takes IRQs.
+    IRQ_HEARTBEAT[me].store(now, Ordering::Relaxed);
+
+    for core in smp::online_cores().cores().filter(|&core| core != me) {
+        let lockup = if stale(&IRQ_HEARTBEAT[core], now) {
+            Lockup::Hard
+        } else if stale(&HEARTBEAT[core], now) {
+            Lockup::Soft
+        } else {
+            continue;
+        };
+
+        if !REPORTED[core].swap(true, Ordering::Relaxed) {
+            report(core, lockup, now);
+        }
+    }
+
+    super::kick(me);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Start checking the heartbeats of all online cores.
+///
+/// Every core runs the check, so a stuck core is caught as long as one other core is fine.
+pub fn start() {
+    for core in 0..HEARTBEAT.len() {
+        touch(core);
+        time_manager().set_timeout_periodic_on(core, PERIOD, Box::new(check));
+    }
+}
+
+/// Choose whether a detected lockup panics the kernel, after it has been reported.
+pub fn set_panic_on_lockup(panic: bool) {
+    PANIC_ON_LOCKUP.store(panic, Ordering::Relaxed);
+}
+
+/// Touch both heartbeats of `core`, e.g. when it comes online.
+pub fn touch(core: usize) {
+    let now = now_nanos();
+
+    HEARTBEAT[core].store(now, Ordering::Relaxed);
+    IRQ_HEARTBEAT[core].store(now, Ordering::Relaxed);
+    REPORTED[core].store(false, Ordering::Relaxed);
+}
+
+/// Record that `core` made a scheduling decision and picked `pid`.
+///
+/// Must only be called by `core` itself.
+pub fn scheduled(core: usize, pid: u64) {
+    touch(core);
+    LAST_PID[core].store(pid, Ordering::Relaxed);
+}
+
+/// Record that `core` was interrupted to reschedule.
+///
+/// Must only be called by `core` itself.
+pub fn touch_irq(core: usize) {
+    IRQ_HEARTBEAT[core].store(now_nanos(), Ordering::Relaxed);
+}
+
+/// Print the state of the executing core, which was interrupted in `ec`. Handles `SGI_11`.
+pub fn dump(ec: &ExceptionContext) {
+    let core: usize = core_id();
+    let pid = CURRENT[core].lock(|pid| *pid);
+
+    warn!(
+        "[WATCHDOG] Core{} running PID={:?}, interrupted in:\n{}\n{}",
+        core,
+        pid,
+        ec,
+        ContextBacktrace(ec)
+    );
+
+    DUMPED[core].store(true, Ordering::SeqCst);
+}
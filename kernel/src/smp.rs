@@ -1,35 +1,49 @@
 use core::{ arch::asm, cell::UnsafeCell, time::Duration };
 
-use aarch64_cpu::asm::barrier::{ dmb, dsb, isb };
+use aarch64_cpu::{ asm::barrier::{ dmb, dsb, isb }, registers::SP };
 use alloc::boxed::Box;
 use rand::{ rngs::SmallRng, RngCore, SeedableRng };
-use tock_registers::{ interfaces::Writeable, register_structs, registers::ReadWrite };
+use tock_registers::{ interfaces::{ Readable, Writeable }, register_structs, registers::ReadWrite };
 
 use crate::{
-    cpu::{ core_id, wait_forever },
+    cpu::{ core_id, wait_forever, NR_CPUS },
     drivers::common::MMIODerefWrapper,
     exception::{ self, asynchronous::local_irq_unmask },
     info,
     memory::{ Address, Virtual, __core_activation_address, mmu },
     time::time_manager,
-    scheduler::{ RUNNING, SLEEPING, CURRENT, reschedule_from_context },
+    scheduler::{ RUNNING, SLEEPING, CURRENT, pick_next, reschedule_from_context },
     debug,
     random,
-    thread::{ reschedule, Thread, __switch_to, thread },
+    exception::arch_exception::ExceptionContext,
+    thread::{ reschedule, __switch_to },
     synchronization::interface::Mutex,
 };
 
+mod call;
+mod hotplug;
+
+pub use call::{ call_on, call_on_many, call_on_others, handle_calls, stop_others };
+pub use hotplug::{
+    is_offline, mark_online, offline_core, online_core, online_cores, park_if_requested,
+};
+
 register_structs! {
     #[allow(non_snake_case)]
-    ///Regblock
+    /// Spin table of the firmware. Each core waits for its entry to hold an entry point.
     pub RegisterBlock {
-        (0x00 => ONE: ReadWrite<u64>),
-        (0x08 => TWO: ReadWrite<u64>),
-        (0x10 => THREE: ReadWrite<u64>),
-        (0x18 => @END),
+        (0x00 => RELEASE_ADDR: [ReadWrite<u64>; NR_CPUS]),
+        (0x20 => @END),
     }
 }
 
+/// `register_structs!` needs a literal for the end of `RegisterBlock`. The RPi4 firmware's spin
+/// table at 0xd8 has one 8 byte release address per core for its four cores, hence 0x20.
+const _: () = assert!(NR_CPUS * 8 == 0x20);
+
+/// Offset of the spin table in the page at `__core_activation_address`.
+const SPIN_TABLE_OFFSET: u64 = 0xd8;
+
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
 extern "Rust" {
@@ -38,28 +52,29 @@ extern "Rust" {
 
 #[no_mangle]
 unsafe fn kernel_init_secondary() -> ! {
+    // Cores brought back online start over on the same stack.
+    hotplug::record_init_stack(SP.get());
+
     exception::handling_init();
+    crate::thread::tls::init();
+    mark_online();
+    crate::time::init_secondary();
 
     // Unmask interrupts on the current CPU core.
     local_irq_unmask();
 
-    let entry_point = thread as *const () as u64;
-
     let core: usize = core_id();
 
     info!("Running Thread list for Core{}:\n{}", core, RUNNING[core]);
 
     CURRENT[core].lock(|cur_pid| {
-        let mut wasted = Thread::new(entry_point);
-        let next_thread: &mut Thread = RUNNING[core].next().expect("No next thread found!");
-
-        *cur_pid = Some(next_thread.get_pid());
-        info!(
-            "Switching to thread PID={}\n {}",
-            next_thread.get_pid(),
-            next_thread.get_ex_context()
-        );
-        __switch_to(wasted.get_ex_context(), next_thread.get_ex_context());
+        // Never resumed. It lives on the init stack, which the core reuses when it comes back.
+        let mut wasted: ExceptionContext = core::mem::zeroed();
+        let (next_pid, next_ctx) = pick_next(core, None);
+
+        *cur_pid = Some(next_pid);
+        info!("Switching to thread PID={}\n {}", next_pid, &*next_ctx);
+        __switch_to(&mut wasted, &mut *next_ctx);
     });
 
     wait_forever();
@@ -78,9 +93,10 @@ pub unsafe fn start_core(core_id: u8) {
 
     info!("Core {} starting with function at address {:#x}", core_id, start_f_address);
 
-    let mut core_wakeup_addr: u64 = (unsafe { __core_activation_address.get() as u64 }) + 0xe0;
+    let spin_table: u64 = (unsafe { __core_activation_address.get() as u64 }) + SPIN_TABLE_OFFSET;
+    let core_wakeup_addr = spin_table + 8 * (core_id as u64);
     info!("Core Wakeup addr: {:#x}", core_wakeup_addr);
-    let cores: Registers = Registers::new(Address::<Virtual>::new(core_wakeup_addr as usize));
+    let cores: Registers = Registers::new(Address::<Virtual>::new(spin_table as usize));
 
     let phaddr = mmu
         ::try_kernel_virt_addr_to_phys_addr(Address::<Virtual>::new(start_f_address))
@@ -89,20 +105,10 @@ pub unsafe fn start_core(core_id: u8) {
 
     info!("PhysAddr of startSecondary({:#x}) => {:#x}", start_f_address, phaddr);
 
-    match core_id {
-        1 => {
-            cores.ONE.set(phaddr as u64);
-        }
-        2 => {
-            cores.TWO.set(phaddr as u64);
-            core_wakeup_addr += 0x8;
-        }
-        3 => {
-            cores.THREE.set(phaddr as u64);
-            core_wakeup_addr += 0x10;
-        }
-        _ => panic!("Can't start other cores"),
+    if core_id == 0 || core_id as usize >= NR_CPUS {
+        panic!("Can't start other cores");
     }
+    cores.RELEASE_ADDR[core_id as usize].set(phaddr as u64);
 
     unsafe {
         asm!(
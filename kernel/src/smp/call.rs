@@ -0,0 +1,136 @@
+//! Cross-core function calls.
+//!
+//! A call is queued on every target core, which is then interrupted with `SGI_10` and runs the
+//! closure from its IRQ handler. The calling core runs the closure itself if it is one of the
+//! targets. Callers can wait until all targets are done, or move on right away.
+//!
+//! A core waiting for its calls to complete keeps running the calls queued for it meanwhile, so two
+//! cores calling each other at the same time cannot deadlock, not even with IRQs masked.
+
+use super::online_cores;
+use crate::{
+    cpu::{core_id, wait_forever},
+    drivers::get_gic,
+    exception::asynchronous::{exec_with_irq_masked, irq_map, local_irq_mask},
+    per_cpu,
+    scheduler::preempt,
+    synchronization::{interface::Mutex, IRQSafeLock},
+    thread::Affinity,
+};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc};
+use core::{
+    hint,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct Call {
+    func: Box<dyn Fn() + Send + Sync>,
+
+    /// Number of target cores that did not run `func` yet.
+    pending: AtomicUsize,
+}
+
+per_cpu! {
+    /// Calls waiting to be run by each core.
+    static CALLS: IRQSafeLock<VecDeque<Arc<Call>>> = IRQSafeLock::new(VecDeque::new());
+}
+
+/// Set once a core panicked. All other cores stop on their next `SGI_10`.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Run all calls queued for `core`. IRQs must be masked.
+pub(super) fn run_calls(core: usize) {
+    while let Some(call) = CALLS[core].lock(|calls| calls.pop_front()) {
+        (call.func)();
+        call.pending.fetch_sub(1, Ordering::Release);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Run `f` on every core in `cores` that is online, the executing one included.
+///
+/// `f` runs with IRQs masked and must not block. With `wait` set, returns only once all targets
+/// are done.
+pub fn call_on_many(cores: Affinity, f: impl Fn() + Send + Sync + 'static, wait: bool) {
+    let call = Arc::new(Call {
+        func: Box::new(f),
+        pending: AtomicUsize::new(0),
+    });
+
+    // Keeps the thread from moving to one of the targets while queueing.
+    preempt::without_preemption(|| {
+        let me: usize = core_id();
+        let targets = online_cores()
+            .cores()
+            .filter(|&core| core != me && cores.contains(core))
+            .fold(0, |targets, core| {
+                call.pending.fetch_add(1, Ordering::Relaxed);
+                CALLS[core].lock(|calls| calls.push_back(call.clone()));
+
+                targets | (1 << core)
+            });
+
+        if targets != 0 {
+            unsafe { get_gic().send_sgi_to(irq_map::SGI_10, targets) }
+        }
+
+        if cores.contains(me) {
+            exec_with_irq_masked(|| (call.func)());
+        }
+    });
+
+    if wait {
+        while call.pending.load(Ordering::Acquire) != 0 {
+            exec_with_irq_masked(|| run_calls(core_id()));
+            hint::spin_loop();
+        }
+    }
+}
+
+/// Run `f` on `core`. See `call_on_many()`.
+pub fn call_on(core: usize, f: impl Fn() + Send + Sync + 'static, wait: bool) {
+    call_on_many(Affinity::single(core), f, wait)
+}
+
+/// Run `f` on every other online core. See `call_on_many()`.
+pub fn call_on_others(f: impl Fn() + Send + Sync + 'static, wait: bool) {
+    let me: usize = core_id();
+    let others = online_cores().cores().filter(|&core| core != me);
+
+    call_on_many(Affinity::from_cores(others), f, wait)
+}
+
+/// Halt all other online cores. Used when panicking.
+///
+/// Does not allocate or take any lock, so it works no matter what the executing core was doing.
+pub fn stop_others() {
+    let me: usize = core_id();
+    let others = online_cores().bits() & !(1 << me);
+    if others == 0 {
+        return;
+    }
+
+    STOPPING.store(true, Ordering::SeqCst);
+    unsafe { get_gic().send_sgi_to(irq_map::SGI_10, others) }
+}
+
+/// Handle `SGI_10` on the executing core.
+pub fn handle_calls() {
+    if STOPPING.load(Ordering::SeqCst) {
+        local_irq_mask();
+        wait_forever();
+    }
+
+    run_calls(core_id());
+}
@@ -0,0 +1,230 @@
+//! CPU hotplug.
+//!
+//! Secondary cores can be taken offline and brought back while the kernel runs. A core asked to go
+//! offline hands all of its threads except the idle thread over to the online cores at its next
+//! scheduling decision. Once only the idle thread is left, it runs the calls still queued for it,
+//! hands the timeouts not pinned to it over to the boot core and parks in a WFE loop with IRQs
+//! masked.
+//!
+//! Bringing a parked core back makes it leave the loop and run `kernel_init_secondary()` again, on
+//! the stack it started with. A core that never ran is started through the spin table instead.
+//!
+//! The boot core always stays online.
+
+use super::{kernel_init_secondary, start_core};
+use crate::{
+    cpu::{core_id, jump_with_stack, BOOT_CORE_ID, NR_CPUS},
+    info, per_cpu,
+    scheduler::{self, balance, edf, kick, watchdog},
+    synchronization::rcu,
+    thread::Affinity,
+    time::{self, time_manager},
+};
+use aarch64_cpu::asm::{
+    self,
+    barrier::{dsb, SY},
+};
+use core::{
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The core was never started.
+const DOWN: u8 = 0;
+
+/// The core schedules threads and takes calls.
+const ONLINE: u8 = 1;
+
+/// The core was asked to go offline and is handing over its threads.
+const PARKING: u8 = 2;
+
+/// The core waits in `park_if_requested()` to be brought back.
+const PARKED: u8 = 3;
+
+/// The core was asked to come back and did not reach `mark_online()` yet.
+const STARTING: u8 = 4;
+
+/// How long to wait for a core to go offline or come up.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long to wait before checking the state of a core again.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+per_cpu! {
+    /// Hotplug state of each core.
+    static STATE: AtomicU8 = AtomicU8::new(DOWN);
+
+    /// Stack pointer `kernel_init_secondary()` first ran with on each core, reused when it comes
+    /// back.
+    static INIT_SP: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Bit `n` is set while core `n` is online.
+static ONLINE_CORES: AtomicU8 = AtomicU8::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Poll `done` until it returns true or `TIMEOUT` passed. Returns whether it succeeded.
+fn wait_until(done: impl Fn() -> bool) -> bool {
+    let deadline = time_manager().uptime() + TIMEOUT;
+
+    while !done() {
+        if time_manager().uptime() >= deadline {
+            return false;
+        }
+        time_manager().spin_for(POLL_INTERVAL);
+    }
+
+    true
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Remember the stack pointer the executing core entered `kernel_init_secondary()` with.
+///
+/// Only the first call per core has an effect.
+pub fn record_init_stack(sp: u64) {
+    let _ =
+        INIT_SP[core_id::<usize>()].compare_exchange(0, sp, Ordering::Relaxed, Ordering::Relaxed);
+}
+
+/// Record that the executing core has its IRQ handling set up and can take calls from now on.
+///
+/// Must be called before the core unmasks IRQs.
+pub fn mark_online() {
+    let core: usize = core_id();
+
+    // Its heartbeats are stale if it was offline before.
+    watchdog::touch(core);
+    rcu::online(core);
+    STATE[core].store(ONLINE, Ordering::SeqCst);
+    ONLINE_CORES.fetch_or(1 << core, Ordering::SeqCst);
+}
+
+/// Cores that are online.
+pub fn online_cores() -> Affinity {
+    Affinity::from_bits(ONLINE_CORES.load(Ordering::SeqCst))
+}
+
+/// Whether `core` is going or has gone offline. No threads may be queued on it.
+pub fn is_offline(core: usize) -> bool {
+    matches!(STATE[core].load(Ordering::SeqCst), PARKING | PARKED)
+}
+
+/// Take `core` offline, moving its threads to the online cores.
+///
+/// Fails if `core` is the boot core, is not online, has real-time threads admitted or did not park
+/// in time. In the latter case it stays online.
+pub fn offline_core(core: usize) -> Result<(), &'static str> {
+    if core >= NR_CPUS {
+        return Err("No such core");
+    }
+    if core == BOOT_CORE_ID as usize {
+        return Err("The boot core cannot go offline");
+    }
+
+    STATE[core]
+        .compare_exchange(ONLINE, PARKING, Ordering::SeqCst, Ordering::SeqCst)
+        .map_err(|_| "Core is not online")?;
+
+    // Checked only now, so that no thread can be admitted meanwhile. Sleeping ones count as well.
+    // The core does not park while any are admitted, so withdrawing the request only fails if the
+    // last one exited meanwhile.
+    if edf::has_admitted(core)
+        && STATE[core]
+            .compare_exchange(PARKING, ONLINE, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    {
+        return Err("Real-time threads cannot leave their core");
+    }
+    ONLINE_CORES.fetch_and(!(1 << core), Ordering::SeqCst);
+
+    // Interrupting the core makes it hand over its threads and park on the way out of the IRQ.
+    let parked = wait_until(|| {
+        kick(core);
+        STATE[core].load(Ordering::SeqCst) == PARKED
+    });
+
+    // The core might still park while the request is withdrawn, so only one of both can win.
+    if !parked
+        && STATE[core]
+            .compare_exchange(PARKING, ONLINE, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    {
+        ONLINE_CORES.fetch_or(1 << core, Ordering::SeqCst);
+        return Err("Core did not go offline in time");
+    }
+
+    info!("Core{} is offline", core);
+    Ok(())
+}
+
+/// Bring `core` online, either starting it for the first time or releasing it from its parking
+/// loop.
+///
+/// Waits until the core reached `kernel_init_secondary()` and fails if it did not in time.
+pub fn online_core(core: usize) -> Result<(), &'static str> {
+    if core >= NR_CPUS {
+        return Err("No such core");
+    }
+
+    match STATE[core].compare_exchange(PARKED, STARTING, Ordering::SeqCst, Ordering::SeqCst) {
+        Ok(_) => {
+            dsb(SY);
+            asm::sev();
+        }
+        Err(DOWN) => unsafe { start_core(core as u8) },
+        Err(ONLINE) => return Ok(()),
+        Err(_) => return Err("Core is busy going offline or coming up"),
+    }
+
+    if !wait_until(|| STATE[core].load(Ordering::SeqCst) == ONLINE) {
+        return Err("Core did not come up in time");
+    }
+
+    info!("Core{} is online", core);
+    Ok(())
+}
+
+/// Park the executing core if it was asked to go offline, has handed over all of its threads and has
+/// no real-time threads admitted.
+///
+/// Called on the way out of every IRQ, after the IRQ has been completed at the interrupt
+/// controller. Does not return if the core parks. Once it is brought back, it starts over in
+/// `kernel_init_secondary()`.
+pub fn park_if_requested() {
+    let core: usize = core_id();
+    if STATE[core].load(Ordering::SeqCst) != PARKING
+        || !scheduler::is_evacuated(core)
+        || edf::has_admitted(core)
+    {
+        return;
+    }
+
+    if STATE[core]
+        .compare_exchange(PARKING, PARKED, Ordering::SeqCst, Ordering::SeqCst)
+        .is_err()
+    {
+        return;
+    }
+
+    // Calls queued before the core left the online mask are still waited for.
+    super::call::run_calls(core);
+    rcu::offline(core);
+    balance::set_idle(core, false);
+    time::offline_secondary();
+
+    while STATE[core].load(Ordering::SeqCst) != STARTING {
+        asm::wfe();
+    }
+
+    unsafe { jump_with_stack(kernel_init_secondary, INIT_SP[core].load(Ordering::Relaxed)) }
+}
@@ -10,7 +10,20 @@
 //!   - <https://stackoverflow.com/questions/59428096/understanding-the-send-trait>
 //!   - <https://doc.rust-lang.org/std/cell/index.html>
 
-use core::cell::UnsafeCell;
+use core::{
+    cell::UnsafeCell,
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+mod blocking;
+pub mod rcu;
+mod rwlock;
+mod seqlock;
+
+pub use blocking::{BlockingLock, Condvar, Semaphore, WaitQueue};
+pub use rwlock::RwSpinLock;
+pub use seqlock::IRQSafeSeqLock;
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -25,33 +38,48 @@ pub mod interface {
         type Data;
 
         /// Locks the mutex and grants the closure temporary mutable access to the wrapped data.
-        fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R;
+        ///
+        /// The reference cannot escape the closure, so the data is only ever touched while the
+        /// lock is held.
+        fn lock<R>(&self, f: impl for<'b> FnOnce(&'b mut Self::Data) -> R) -> R;
     }
 
     /// A reader-writer exclusion type.
     ///
     /// The implementing object allows either a number of readers or at most one writer at any point
     /// in time.
+    ///
+    /// References handed to the closures must not outlive them, since the access is over once the
+    /// closure returns.
     pub trait ReadWriteEx {
         /// The type of encapsulated data.
         type Data;
 
         /// Grants temporary mutable access to the encapsulated data.
-        fn write<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R;
+        fn write<'a, R>(&'a self, f: impl FnOnce(&mut Self::Data) -> R) -> R;
 
         /// Grants temporary immutable access to the encapsulated data.
-        fn read<'a, R>(&'a self, f: impl FnOnce(&'a Self::Data) -> R) -> R;
+        fn read<'a, R>(&'a self, f: impl FnOnce(&Self::Data) -> R) -> R;
     }
 }
 
-/// A pseudo-lock for teaching purposes.
-///
-/// In contrast to a real Mutex implementation, does not protect against concurrent access from
-/// other cores to the contained data. This part is preserved for later lessons.
+/// A ticket spin lock that also masks IRQs on the executing core while it is held.
 ///
-/// The lock will only be used as long as it is safe to do so, i.e. as long as the kernel is
-/// executing on a single core.
+/// Cores are granted the lock in the order in which they started waiting for it, so none of them
+/// can starve. IRQs are masked before queueing up, which keeps an IRQ handler on the same core from
+/// spinning on a lock its interrupted code holds.
 pub struct IRQSafeLock<T> where T: ?Sized {
+    next_ticket: AtomicUsize,
+    now_serving: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+/// A lock for per-core data that only masks IRQs on the executing core.
+///
+/// Does not protect against other cores. Only the core owning the data may ever lock it. In
+/// exchange, it may stay locked across a context switch: the thread switched to leaves the closure
+/// of the thread it replaced, possibly on another core after a migration.
+pub struct CoreLocalLock<T> where T: ?Sized {
     data: UnsafeCell<T>,
 }
 
@@ -62,6 +90,7 @@ pub struct SpinLock<T> where T: ?Sized {
 /// A pseudo-lock that is RW during the single-core kernel init phase and RO afterwards.
 ///
 /// Intended to encapsulate data that is populated during kernel init when no concurrency exists.
+#[repr(transparent)]
 pub struct InitStateLock<T> where T: ?Sized {
     data: UnsafeCell<T>,
 }
@@ -74,6 +103,20 @@ unsafe impl<T> Send for IRQSafeLock<T> where T: ?Sized + Send {}
 unsafe impl<T> Sync for IRQSafeLock<T> where T: ?Sized + Send {}
 
 impl<T> IRQSafeLock<T> {
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+unsafe impl<T> Send for CoreLocalLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for CoreLocalLock<T> where T: ?Sized + Send {}
+
+impl<T> CoreLocalLock<T> {
     /// Create an instance.
     pub const fn new(data: T) -> Self {
         Self {
@@ -118,9 +161,30 @@ use crate::{ exception, state };
 impl<T> interface::Mutex for IRQSafeLock<T> {
     type Data = T;
 
-    fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
-        // In a real lock, there would be code encapsulating this line that ensures that this
-        // mutable reference will ever only be given out once at a time.
+    fn lock<R>(&self, f: impl for<'b> FnOnce(&'b mut Self::Data) -> R) -> R {
+        exception::asynchronous::exec_with_irq_masked(|| {
+            let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+            while self.now_serving.load(Ordering::Acquire) != ticket {
+                hint::spin_loop();
+            }
+
+            // The ticket grants exclusive access until `now_serving` moves on.
+            let data = unsafe { &mut *self.data.get() };
+            let ret = f(data);
+
+            self.now_serving
+                .store(ticket.wrapping_add(1), Ordering::Release);
+
+            ret
+        })
+    }
+}
+
+impl<T> interface::Mutex for CoreLocalLock<T> {
+    type Data = T;
+
+    fn lock<R>(&self, f: impl for<'b> FnOnce(&'b mut Self::Data) -> R) -> R {
+        // Other cores never touch the data, so masking IRQs on this one is enough.
         let data = unsafe { &mut *self.data.get() };
 
         // Execute the closure while IRQs are masked.
@@ -131,7 +195,7 @@ impl<T> interface::Mutex for IRQSafeLock<T> {
 impl<T> interface::Mutex for SpinLock<T> {
     type Data = T;
 
-    fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+    fn lock<R>(&self, f: impl for<'b> FnOnce(&'b mut Self::Data) -> R) -> R {
         // In a real lock, there would be code encapsulating this line that ensures that this
         // mutable reference will ever only be given out once at a time.
         let lock = self.data.lock();
@@ -145,7 +209,7 @@ impl<T> interface::Mutex for SpinLock<T> {
 impl<T> interface::ReadWriteEx for InitStateLock<T> {
     type Data = T;
 
-    fn write<'a, R>(&'a self, f: impl FnOnce(&'a mut Self::Data) -> R) -> R {
+    fn write<'a, R>(&'a self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
         assert!(
             state::state_manager().is_init(),
             "InitStateLock::write called after kernel init phase"
@@ -160,7 +224,7 @@ impl<T> interface::ReadWriteEx for InitStateLock<T> {
         f(data)
     }
 
-    fn read<'a, R>(&'a self, f: impl FnOnce(&'a Self::Data) -> R) -> R {
+    fn read<'a, R>(&'a self, f: impl FnOnce(&Self::Data) -> R) -> R {
         let data = unsafe { &*self.data.get() };
 
         f(data)
@@ -0,0 +1,201 @@
+//! Synchronization primitives that park waiting threads instead of spinning.
+//!
+//! All of them must only be used from thread context, never from IRQ handlers or before the
+//! first thread got scheduled on the executing core.
+
+use super::interface;
+use crate::{scheduler::ThreadQueue, thread};
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A queue of parked threads.
+pub struct WaitQueue {
+    threads: ThreadQueue,
+}
+
+/// A mutex that parks contending threads in a wait queue.
+pub struct BlockingLock<T>
+where
+    T: ?Sized,
+{
+    locked: AtomicBool,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+/// A counting semaphore.
+pub struct Semaphore {
+    count: AtomicUsize,
+    waiters: WaitQueue,
+}
+
+/// A condition variable, used together with a `BlockingLock`.
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl WaitQueue {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            threads: ThreadQueue::new(),
+        }
+    }
+
+    /// Park the current thread if `condition` holds.
+    ///
+    /// `condition` is evaluated with the queue locked. Wakers must change the state the condition
+    /// depends on before calling `notify_one()` or `notify_all()`, then no wakeup can be lost.
+    pub fn wait_if(&self, condition: impl FnOnce() -> bool) {
+        thread::block_on_if(&self.threads, condition)
+    }
+
+    /// Wake the thread that has been waiting the longest. Returns false if none was waiting.
+    pub fn notify_one(&self) -> bool {
+        thread::wake_one(&self.threads)
+    }
+
+    /// Wake all waiting threads and return how many there were.
+    pub fn notify_all(&self) -> usize {
+        thread::wake_all(&self.threads)
+    }
+}
+
+unsafe impl<T> Send for BlockingLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for BlockingLock<T> where T: ?Sized + Send {}
+
+impl<T> BlockingLock<T> {
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> BlockingLock<T>
+where
+    T: ?Sized,
+{
+    fn acquire(&self) {
+        while self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.waiters.wait_if(|| self.locked.load(Ordering::Relaxed));
+        }
+    }
+
+    fn release(&self) {
+        self.locked.store(false, Ordering::Release);
+        self.waiters.notify_one();
+    }
+}
+
+impl<T> interface::Mutex for BlockingLock<T> {
+    type Data = T;
+
+    fn lock<R>(&self, f: impl for<'b> FnOnce(&'b mut Self::Data) -> R) -> R {
+        self.acquire();
+
+        let data = unsafe { &mut *self.data.get() };
+        let ret = f(data);
+
+        self.release();
+        ret
+    }
+}
+
+impl Semaphore {
+    /// Create an instance with `count` available permits.
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: AtomicUsize::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Take a permit without blocking. Returns false if none was available.
+    pub fn try_down(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |c| c.checked_sub(1))
+            .is_ok()
+    }
+
+    /// Take a permit, parking the current thread until one is available.
+    pub fn down(&self) {
+        while !self.try_down() {
+            self.waiters
+                .wait_if(|| self.count.load(Ordering::Relaxed) == 0);
+        }
+    }
+
+    /// Return a permit and wake one waiter.
+    pub fn up(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.waiters.notify_one();
+    }
+
+    /// Number of currently available permits.
+    pub fn available(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+impl Condvar {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    /// Lock `lock`, wait until `condition` holds for the protected data and then run `f` on it.
+    ///
+    /// While waiting, the lock is released. Releasing it and parking the thread happens as one
+    /// step with respect to `notify_one()`/`notify_all()`.
+    pub fn wait_until<'a, T, R>(
+        &self,
+        lock: &'a BlockingLock<T>,
+        mut condition: impl FnMut(&mut T) -> bool,
+        f: impl FnOnce(&'a mut T) -> R,
+    ) -> R {
+        lock.acquire();
+
+        while !condition(unsafe { &mut *lock.data.get() }) {
+            self.waiters.wait_if(|| {
+                lock.release();
+                true
+            });
+            lock.acquire();
+        }
+
+        let ret = f(unsafe { &mut *lock.data.get() });
+
+        lock.release();
+        ret
+    }
+
+    /// Wake one waiting thread.
+    pub fn notify_one(&self) -> bool {
+        self.waiters.notify_one()
+    }
+
+    /// Wake all waiting threads.
+    pub fn notify_all(&self) -> usize {
+        self.waiters.notify_all()
+    }
+}
@@ -0,0 +1,272 @@
+//! Read-copy-update.
+//!
+//! Readers only disable preemption, so they never wait for anyone and never write to shared memory.
+//! Writers publish a new version of the data and retire the old one, which is freed once every core
+//! that might still be reading it went through a quiescent state.
+//!
+//! A core passes a quiescent state whenever it switches threads, because threads must not switch
+//! away inside a read-side critical section. A grace period ends once every core passed one after
+//! the grace period started. Cores that would not switch on their own, e.g. tickless ones, are
+//! kicked.
+//!
+//! Readers are the GIC dispatching IRQs through its handler table and the thread registry, which
+//! doubles as PID index. A lookup by PID only locks the queue the index points to.
+
+use super::{interface::Mutex, IRQSafeLock};
+use crate::{
+    cpu::core_id,
+    per_cpu,
+    scheduler::{kick, preempt},
+    thread,
+};
+use alloc::{boxed::Box, vec::Vec};
+use core::{
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+/// How long `synchronize_rcu()` sleeps before checking the grace period again.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Number of the most recent grace period.
+static GRACE_PERIOD: AtomicU64 = AtomicU64::new(0);
+
+per_cpu! {
+    /// Most recent grace period each core has seen at one of its quiescent states.
+    static QUIESCENT: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Bit `n` is set while core `n` schedules threads. Other cores have no readers.
+static ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// Callbacks together with the grace period they are waiting for.
+static CALLBACKS: IRQSafeLock<Vec<(u64, Callback)>> = IRQSafeLock::new(Vec::new());
+
+/// Number of entries in `CALLBACKS`, so that quiescent states can skip the lock.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Owner of a retired version, moved into the callback that frees it.
+struct Retired<T>(*mut T);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A pointer to RCU-protected data.
+///
+/// Reads do not take any lock. Updates are serialized among each other and never wait for
+/// readers.
+pub struct Rcu<T> {
+    current: AtomicPtr<T>,
+    update_lock: IRQSafeLock<()>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+unsafe impl<T> Send for Retired<T> where T: Send {}
+
+/// Start a new grace period and return its number.
+fn start_grace_period() -> u64 {
+    GRACE_PERIOD.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+/// Whether every online core passed a quiescent state during grace period `gp`.
+fn completed(gp: u64) -> bool {
+    let online = ONLINE.load(Ordering::SeqCst);
+
+    QUIESCENT
+        .iter()
+        .enumerate()
+        .filter(|(core, _)| online & (1 << core) != 0)
+        .all(|(_, quiescent)| quiescent.load(Ordering::SeqCst) >= gp)
+}
+
+/// Interrupt every online core that did not pass a quiescent state during grace period `gp` yet.
+fn kick_laggards(gp: u64) {
+    let online = ONLINE.load(Ordering::SeqCst);
+
+    QUIESCENT
+        .iter()
+        .enumerate()
+        .filter(|(core, quiescent)| {
+            online & (1 << core) != 0 && quiescent.load(Ordering::SeqCst) < gp
+        })
+        .for_each(|(core, _)| kick(core));
+}
+
+/// Record that `core` is not reading right now and has to be waited for from now on.
+fn mark_quiescent(core: usize) {
+    QUIESCENT[core].store(GRACE_PERIOD.load(Ordering::SeqCst), Ordering::SeqCst);
+    ONLINE.fetch_or(1 << core, Ordering::SeqCst);
+}
+
+/// Run all callbacks whose grace period has ended.
+fn run_callbacks() {
+    if PENDING.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+
+    let ready: Vec<Callback> = CALLBACKS.lock(|callbacks| {
+        let mut ready = Vec::new();
+        let mut i = 0;
+        while i < callbacks.len() {
+            if completed(callbacks[i].0) {
+                ready.push(callbacks.swap_remove(i).1);
+            } else {
+                i += 1;
+            }
+        }
+        PENDING.fetch_sub(ready.len(), Ordering::SeqCst);
+
+        ready
+    });
+
+    for callback in ready {
+        callback();
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Enter a read-side critical section.
+///
+/// Calls nest. The thread must not block or yield until the matching `rcu_read_unlock()`.
+pub fn rcu_read_lock() {
+    preempt::preempt_disable();
+}
+
+/// Leave a read-side critical section.
+pub fn rcu_read_unlock() {
+    preempt::preempt_enable();
+}
+
+/// Run `f` in a read-side critical section.
+pub fn rcu_read<R>(f: impl FnOnce() -> R) -> R {
+    rcu_read_lock();
+    let ret = f();
+    rcu_read_unlock();
+
+    ret
+}
+
+/// Run `callback` once all read-side critical sections in progress have ended.
+///
+/// The callback runs during a later context switch on some core, with IRQs masked. It must not
+/// block. May be called from IRQ handlers as well.
+pub fn call_rcu(callback: impl FnOnce() + Send + 'static) {
+    let gp = start_grace_period();
+
+    CALLBACKS.lock(|callbacks| callbacks.push((gp, Box::new(callback))));
+    PENDING.fetch_add(1, Ordering::SeqCst);
+
+    kick_laggards(gp);
+}
+
+/// Wait until all read-side critical sections in progress have ended.
+///
+/// Must be called from a thread, outside of any read-side critical section.
+pub fn synchronize_rcu() {
+    let core: usize = core_id();
+    assert!(
+        !preempt::is_disabled(core),
+        "synchronize_rcu() called with preemption disabled on Core{}",
+        core
+    );
+
+    let gp = start_grace_period();
+
+    // The calling thread is not reading, so the core it runs on is quiescent right now.
+    QUIESCENT.with_local(|quiescent| quiescent.fetch_max(gp, Ordering::SeqCst));
+
+    while !completed(gp) {
+        kick_laggards(gp);
+        thread::sleep_for(POLL_INTERVAL);
+    }
+}
+
+/// Report a quiescent state of `core`.
+///
+/// Called by the scheduler on every context switch. Must only be called by `core` itself.
+pub fn quiescent_state(core: usize) {
+    mark_quiescent(core);
+    run_callbacks();
+}
+
+/// Start waiting for `core` in grace periods, because it comes online.
+///
+/// Must only be called by `core` itself, before it unmasks IRQs. IRQ handlers may read from then
+/// on, even before the core first switches threads.
+pub fn online(core: usize) {
+    mark_quiescent(core);
+}
+
+/// Stop waiting for `core` in grace periods, because it goes offline.
+///
+/// Must only be called by `core` itself, outside of any read-side critical section. `online()`
+/// brings it back.
+pub fn offline(core: usize) {
+    ONLINE.fetch_and(!(1 << core), Ordering::SeqCst);
+}
+
+impl<T> Rcu<T>
+where
+    T: Send + Sync + 'static,
+{
+    /// Create an instance that does not point to any data yet.
+    pub const fn empty() -> Self {
+        Self {
+            current: AtomicPtr::new(ptr::null_mut()),
+            update_lock: IRQSafeLock::new(()),
+        }
+    }
+
+    /// Grants the closure access to the current version of the data, if there is one.
+    pub fn read<R>(&self, f: impl FnOnce(Option<&T>) -> R) -> R {
+        rcu_read(|| {
+            let current = self.current.load(Ordering::Acquire);
+
+            f(unsafe { current.as_ref() })
+        })
+    }
+
+    /// Replace the data with the version `f` derives from the current one.
+    ///
+    /// The old version is freed after a grace period.
+    pub fn update(&self, f: impl FnOnce(Option<&T>) -> T) {
+        let old = self.update_lock.lock(|_| {
+            let old = self.current.load(Ordering::Acquire);
+            let new = Box::into_raw(Box::new(f(unsafe { old.as_ref() })));
+            self.current.store(new, Ordering::Release);
+
+            old
+        });
+
+        if !old.is_null() {
+            let retired = Retired(old);
+            call_rcu(move || {
+                let retired = retired;
+                drop(unsafe { Box::from_raw(retired.0) });
+            });
+        }
+    }
+}
+
+impl<T> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        let current = *self.current.get_mut();
+        if !current.is_null() {
+            drop(unsafe { Box::from_raw(current) });
+        }
+    }
+}
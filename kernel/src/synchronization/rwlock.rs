@@ -0,0 +1,118 @@
+//! Reader-writer spin locks.
+//!
+//! Any number of readers may hold the lock at the same time, a writer holds it alone. A waiting
+//! writer keeps new readers out, so that a steady stream of readers cannot starve it.
+
+use super::interface;
+use core::{
+    cell::UnsafeCell,
+    hint,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Set while a writer holds the lock or waits for the readers to leave. The other bits count the
+/// readers.
+const WRITER: usize = 1 << (usize::BITS - 1);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A reader-writer spin lock.
+///
+/// Must not be used from IRQ handlers.
+pub struct RwSpinLock<T>
+where
+    T: ?Sized,
+{
+    state: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl<T> RwSpinLock<T>
+where
+    T: ?Sized,
+{
+    fn acquire_read(&self) {
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+
+            if state & WRITER == 0
+                && self
+                    .state
+                    .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                return;
+            }
+
+            hint::spin_loop();
+        }
+    }
+
+    fn release_read(&self) {
+        self.state.fetch_sub(1, Ordering::Release);
+    }
+
+    fn acquire_write(&self) {
+        // Claim the writer bit first, then wait for the readers that were already in.
+        while self.state.fetch_or(WRITER, Ordering::Acquire) & WRITER != 0 {
+            hint::spin_loop();
+        }
+
+        while self.state.load(Ordering::Acquire) != WRITER {
+            hint::spin_loop();
+        }
+    }
+
+    fn release_write(&self) {
+        self.state.store(0, Ordering::Release);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+unsafe impl<T> Send for RwSpinLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for RwSpinLock<T> where T: ?Sized + Send + Sync {}
+
+impl<T> RwSpinLock<T> {
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> interface::ReadWriteEx for RwSpinLock<T> {
+    type Data = T;
+
+    fn write<'a, R>(&'a self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
+        self.acquire_write();
+
+        let ret = f(unsafe { &mut *self.data.get() });
+        self.release_write();
+
+        ret
+    }
+
+    fn read<'a, R>(&'a self, f: impl FnOnce(&Self::Data) -> R) -> R {
+        self.acquire_read();
+
+        let ret = f(unsafe { &*self.data.get() });
+        self.release_read();
+
+        ret
+    }
+}
@@ -0,0 +1,148 @@
+//! Sequence locks.
+//!
+//! Readers never write to the lock. They copy the data and retry if a writer was active meanwhile,
+//! which makes reads cheap and keeps writers from ever waiting for readers. Suited for small `Copy`
+//! data that is read far more often than it is written.
+
+use super::interface;
+use crate::exception;
+use core::{
+    cell::UnsafeCell,
+    hint, ptr,
+    sync::atomic::{self, AtomicUsize, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A sequence lock.
+///
+/// Must not be written from IRQ handlers, see `IRQSafeSeqLock` for that. Reading is allowed
+/// everywhere.
+pub struct SeqLock<T> {
+    /// Odd while a writer is active.
+    sequence: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+/// A sequence lock whose writers mask IRQs on the executing core.
+///
+/// Keeps an IRQ handler from reading on the core of an interrupted writer, where it would retry
+/// forever.
+pub struct IRQSafeSeqLock<T> {
+    inner: SeqLock<T>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+unsafe impl<T> Send for SeqLock<T> where T: Copy + Send {}
+unsafe impl<T> Sync for SeqLock<T> where T: Copy + Send {}
+
+impl<T> SeqLock<T>
+where
+    T: Copy,
+{
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            sequence: AtomicUsize::new(0),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Return a consistent copy of the data.
+    pub fn get(&self) -> T {
+        loop {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            if sequence & 1 != 0 {
+                hint::spin_loop();
+                continue;
+            }
+
+            // May observe a half-written value, which is discarded below.
+            let data = unsafe { ptr::read_volatile(self.data.get()) };
+
+            atomic::fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) == sequence {
+                return data;
+            }
+        }
+    }
+}
+
+impl<T> interface::ReadWriteEx for SeqLock<T>
+where
+    T: Copy,
+{
+    type Data = T;
+
+    fn write<'a, R>(&'a self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
+        // Making the sequence odd excludes other writers as well.
+        let mut sequence = self.sequence.load(Ordering::Relaxed);
+        loop {
+            if sequence & 1 == 0 {
+                match self.sequence.compare_exchange_weak(
+                    sequence,
+                    sequence + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => sequence = current,
+                }
+            } else {
+                hint::spin_loop();
+                sequence = self.sequence.load(Ordering::Relaxed);
+            }
+        }
+
+        // Keep the data writes behind the odd sequence number.
+        atomic::fence(Ordering::Release);
+
+        let ret = f(unsafe { &mut *self.data.get() });
+        self.sequence
+            .store(sequence.wrapping_add(2), Ordering::Release);
+
+        ret
+    }
+
+    /// Runs `f` on a copy of the data, see `get()`.
+    fn read<'a, R>(&'a self, f: impl FnOnce(&Self::Data) -> R) -> R {
+        f(&self.get())
+    }
+}
+
+impl<T> IRQSafeSeqLock<T>
+where
+    T: Copy,
+{
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: SeqLock::new(data),
+        }
+    }
+
+    /// Return a consistent copy of the data.
+    pub fn get(&self) -> T {
+        self.inner.get()
+    }
+}
+
+impl<T> interface::ReadWriteEx for IRQSafeSeqLock<T>
+where
+    T: Copy,
+{
+    type Data = T;
+
+    fn write<'a, R>(&'a self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
+        exception::asynchronous::exec_with_irq_masked(|| self.inner.write(f))
+    }
+
+    fn read<'a, R>(&'a self, f: impl FnOnce(&Self::Data) -> R) -> R {
+        self.inner.read(f)
+    }
+}
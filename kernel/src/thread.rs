@@ -2,11 +2,12 @@ use core::{
     alloc::{GlobalAlloc, Layout, LayoutError},
     fmt, mem,
     ptr::addr_of_mut,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
     time::Duration,
 };
 
 use aarch64_cpu::registers::{DAIF, ESR_EL1, SPSR_EL1};
+use alloc::{boxed::Box, string::String, sync::Arc};
 use rand::{rngs::SmallRng, RngCore, SeedableRng};
 use tock_registers::{
     interfaces::{Readable, Writeable},
@@ -14,38 +15,229 @@ use tock_registers::{
 };
 
 use crate::{
-    cpu::{core_id, wait_forever},
+    cpu::{core_id, wait_for_interrupt, wait_forever, NR_CPUS},
     debug,
     exception::{
         arch_exception::{EsrEL1, ExceptionContext, SpsrEL1},
         asynchronous::{is_local_irq_masked, local_irq_mask_save, local_irq_restore, print_state},
     },
     info,
-    memory::{self, heap_alloc::kernel_heap_allocator},
+    memory::{
+        self,
+        stack_alloc::{kernel_stack_allocator, Stack, DEFAULT_STACK_SIZE},
+    },
     random,
-    scheduler::{CURRENT, RUNNING, SLEEPING},
-    synchronization::interface::Mutex,
+    scheduler::{
+        balance, bury, detach_runnable,
+        edf::{self, RealTimeState},
+        enqueue, kick, least_loaded_core, pick_next, preempt, scheduling_policy, with_runnable,
+        ThreadNode, ThreadQueue, CURRENT, RT_RUNNING, RUNNING, SLEEPING,
+    },
+    smp,
+    synchronization::{interface::Mutex, WaitQueue},
+    thread_local,
     time::time_manager,
 };
 
+pub mod fp;
+pub mod stats;
+pub mod tls;
+
+use fp::FpState;
+use stats::{ThreadState, ThreadStats};
+use tls::TlsBlock;
+
+/// Static thread priority. Higher values are more important.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Priority(u8);
+
+/// Set of cores a thread may run on. Bit `n` stands for core `n`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Affinity(u8);
+
+/// Exit state shared between a thread and its join handles.
+struct ExitStatus {
+    code: AtomicU64,
+    done: AtomicBool,
+    joiners: WaitQueue,
+}
+
+/// Allows to wait for a thread to end and to retrieve its exit code.
+pub struct JoinHandle {
+    pid: u64,
+    name: String,
+    status: Arc<ExitStatus>,
+}
+
+/// Settings for spawning a thread, for when the `spawn*()` shorthands do not suffice.
+pub struct Builder {
+    name: String,
+    core: Option<usize>,
+    priority: Priority,
+    stack_size: usize,
+}
+
+/// The closure run by a thread created through `spawn()`.
+type ThreadMain = Box<dyn FnOnce() + Send + 'static>;
+
 pub struct Thread {
     pid: u64,
     context: ExceptionContext,
-    original_stack: usize,
+    stack: Stack,
+    /// Thread-local storage, also holding the name. TPIDR_EL1 points here while the thread runs.
+    tls: Box<TlsBlock>,
+    priority: Priority,
+    /// Dynamic priority level, moved around by the scheduling policy. Starts at `priority`.
+    level: u8,
+    /// Time slices used up at the current level without giving up the CPU.
+    slices_at_level: u8,
+    /// Set for threads admitted to the real-time class.
+    rt: Option<RealTimeState>,
+    /// Core whose run queue the thread goes back to when it is woken up.
+    core: usize,
+    affinity: Affinity,
+    /// Core the thread was asked to move to by `migrate()`, applied by the core owning it.
+    migrate_to: Option<usize>,
+    /// CPU time accounting, shared with the thread registry.
+    stats: Arc<ThreadStats>,
+    /// FP/SIMD registers, allocated when the thread first uses FP/SIMD.
+    fp: Option<Box<FpState>>,
+    exit_status: Arc<ExitStatus>,
+}
+
+impl Priority {
+    pub const IDLE: Self = Self(0);
+    pub const LOW: Self = Self(1);
+    pub const NORMAL: Self = Self(2);
+    pub const HIGH: Self = Self(3);
+
+    /// Create an instance, saturating at `Priority::HIGH`.
+    pub const fn new(value: u8) -> Self {
+        if value > Self::HIGH.0 {
+            Self::HIGH
+        } else {
+            Self(value)
+        }
+    }
+
+    pub const fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::IDLE => write!(f, "IDLE"),
+            Self::LOW => write!(f, "LOW"),
+            Self::NORMAL => write!(f, "NORMAL"),
+            _ => write!(f, "HIGH"),
+        }
+    }
+}
+
+impl Affinity {
+    /// All cores.
+    pub const ALL: Self = Self(u8::MAX >> (u8::BITS as usize - NR_CPUS));
+
+    /// Only `core`.
+    pub const fn single(core: usize) -> Self {
+        Self(1 << core)
+    }
+
+    /// The given cores. Cores that do not exist are ignored.
+    pub fn from_cores(cores: impl IntoIterator<Item = usize>) -> Self {
+        let bits = cores
+            .into_iter()
+            .filter(|&core| core < RUNNING.len())
+            .fold(0, |bits, core| bits | (1 << core));
+
+        Self(bits)
+    }
+
+    /// The cores whose bits are set in `bits`.
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(self, core: usize) -> bool {
+        core < u8::BITS as usize && self.0 & (1 << core) != 0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Iterate over the cores in the set.
+    pub fn cores(self) -> impl Iterator<Item = usize> {
+        (0..RUNNING.len()).filter(move |&core| self.contains(core))
+    }
+}
+
+impl fmt::Display for Affinity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#06b}", self.0)
+    }
 }
 
 static PID: AtomicU64 = AtomicU64::new(0);
 
-const STACK_SIZE: usize = 8192;
-const STACK_ALIGN: usize = 4096;
+thread_local! {
+    /// Number of sleeps the thread finished. A timeout only wakes the thread if the sleep it was
+    /// set for is still in progress.
+    static SLEEPS: Arc<AtomicU64> = Arc::new(AtomicU64::new(0));
+}
 
 impl Thread {
-    pub fn new(entry_point: u64) -> Self {
-        let (c, stack) = Self::make_context(entry_point);
+    pub fn new(entry_point: u64, priority: Priority) -> Self {
+        Self::new_with_arg(entry_point, 0, priority)
+    }
+
+    /// Create a thread whose entry point receives `arg` as its first argument.
+    pub fn new_with_arg(entry_point: u64, arg: u64, priority: Priority) -> Self {
+        Self::new_with_stack(entry_point, arg, priority, DEFAULT_STACK_SIZE)
+    }
+
+    /// Create a thread with a stack of at least `stack_size` bytes.
+    ///
+    /// Panics if no stack of that size can be allocated.
+    pub fn new_with_stack(
+        entry_point: u64,
+        arg: u64,
+        priority: Priority,
+        stack_size: usize,
+    ) -> Self {
+        let pid = PID.fetch_add(1, Ordering::Acquire);
+        let stack = kernel_stack_allocator()
+            .alloc(stack_size)
+            .unwrap_or_else(|x| panic!("Cannot allocate stack for PID={}: {}", pid, x));
+        let tls = TlsBlock::new(pid, stack.bottom());
+        // PIDs 0..NR_CPUS are the per-core idle threads created first in `kernel_main`.
+        let idle = pid < NR_CPUS as u64;
+
+        let mut c = Self::make_context(entry_point, &stack);
+        c.gpr[0] = arg;
+        c.tpidr_el1 = &*tls as *const TlsBlock as u64;
+
         let out = Thread {
-            pid: PID.fetch_add(1, Ordering::Acquire),
+            pid,
             context: c,
-            original_stack: stack as usize,
+            stack,
+            tls,
+            priority,
+            level: priority.get(),
+            slices_at_level: 0,
+            rt: None,
+            core: 0,
+            affinity: Affinity::ALL,
+            migrate_to: None,
+            stats: Arc::new(ThreadStats::new(pid, idle)),
+            fp: None,
+            exit_status: Arc::new(ExitStatus::new()),
         };
         out
     }
@@ -58,63 +250,396 @@ impl Thread {
         self.pid
     }
 
-    fn make_context(entry_point: u64) -> (ExceptionContext, *mut u8) {
-        let stack_pointer_low_end;
-        unsafe {
-            stack_pointer_low_end = memory::heap_alloc::kernel_heap_allocator()
-                .alloc(Layout::from_size_align(STACK_SIZE, STACK_ALIGN).unwrap());
+    pub fn name(&self) -> &str {
+        self.tls.name()
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.tls.set_name(name);
+    }
+
+    /// Size of the thread's stack in bytes.
+    pub fn stack_size(&self) -> usize {
+        self.stack.size()
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Change the static priority. Also resets the dynamic level to the new priority.
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+        self.set_level(priority.get());
+    }
+
+    pub fn level(&self) -> u8 {
+        self.level
+    }
+
+    /// Move the thread to another dynamic level and restart its slice accounting.
+    pub fn set_level(&mut self, level: u8) {
+        self.level = level;
+        self.slices_at_level = 0;
+    }
+
+    /// Account one more used up time slice at the current level and return the new count.
+    pub fn charge_slice(&mut self) -> u8 {
+        self.slices_at_level = self.slices_at_level.saturating_add(1);
+        self.slices_at_level
+    }
+
+    pub fn is_realtime(&self) -> bool {
+        self.rt.is_some()
+    }
+
+    pub fn realtime(&self) -> Option<&RealTimeState> {
+        self.rt.as_ref()
+    }
+
+    pub fn realtime_mut(&mut self) -> Option<&mut RealTimeState> {
+        self.rt.as_mut()
+    }
+
+    pub fn set_realtime(&mut self, state: RealTimeState) {
+        self.rt = Some(state);
+    }
+
+    pub fn core(&self) -> usize {
+        self.core
+    }
+
+    pub fn set_core(&mut self, core: usize) {
+        self.core = core;
+    }
+
+    pub fn affinity(&self) -> Affinity {
+        self.affinity
+    }
+
+    /// Restrict the cores the thread may run on.
+    ///
+    /// Does not move the thread. If its current core is no longer allowed, a migration has to be
+    /// requested as well.
+    pub fn set_affinity(&mut self, affinity: Affinity) -> Result<(), &'static str> {
+        if self.is_idle() {
+            return Err("Idle threads are pinned to their core");
+        }
+
+        if affinity.is_empty() {
+            return Err("Affinity mask must contain at least one core");
+        }
+
+        self.affinity = affinity;
+        Ok(())
+    }
+
+    /// Ask the core owning the thread to hand it over to `core`.
+    pub fn request_migration(&mut self, core: usize) -> Result<(), &'static str> {
+        if self.is_idle() {
+            return Err("Idle threads are pinned to their core");
+        }
+
+        if !self.affinity.contains(core) {
+            return Err("Core is not in the thread's affinity mask");
+        }
+
+        self.migrate_to = if core == self.core { None } else { Some(core) };
+        Ok(())
+    }
+
+    pub fn has_pending_migration(&self) -> bool {
+        self.migrate_to.is_some()
+    }
+
+    /// Take the pending migration request, if any.
+    pub fn take_migration(&mut self) -> Option<usize> {
+        self.migrate_to.take()
+    }
+
+    pub fn stats(&self) -> &Arc<ThreadStats> {
+        &self.stats
+    }
+
+    /// Whether the thread has used FP/SIMD.
+    pub fn uses_fp(&self) -> bool {
+        self.fp.is_some()
+    }
+
+    /// Save the FP/SIMD registers of the thread if it uses them. Called when it is switched out.
+    ///
+    /// Does nothing in builds without the `fp` feature.
+    pub fn save_fp(&mut self) {
+        if !cfg!(feature = "fp") {
+            return;
         }
 
-        let mut sp_value = stack_pointer_low_end as u64;
-        sp_value += 8192;
+        if let Some(fp) = &mut self.fp {
+            fp.save();
+        }
+    }
+
+    /// Load the FP/SIMD registers of the thread, or make FP/SIMD trap if it never used them.
+    /// Called when it is switched in.
+    ///
+    /// Does nothing in builds without the `fp` feature, which leave FP/SIMD access as it is.
+    pub fn restore_fp(&self) {
+        if !cfg!(feature = "fp") {
+            return;
+        }
+
+        match &self.fp {
+            Some(fp) => fp.restore(),
+            None => fp::disable(),
+        }
+    }
+
+    /// Return a handle that can be used to wait for the thread to end.
+    pub fn join_handle(&self) -> JoinHandle {
+        JoinHandle {
+            pid: self.pid,
+            name: String::from(self.name()),
+            status: self.exit_status.clone(),
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.stats.is_idle()
+    }
+
+    fn make_context(entry_point: u64, stack: &Stack) -> ExceptionContext {
+        let sp_value = stack.top().as_usize() as u64;
 
         let spsr_el1_init = 0x364;
         //USermode:
         //spsr_el1_init &= 0xFFF8;
-        (
-            ExceptionContext {
-                gpr: [0; 30],
-                lr: entry_point,
-                elr_el1: entry_point,
-                spsr_el1: spsr_el1_init,
-                esr_el1: 0,
-                _res_sp: 0,
-                sp_el0: sp_value,
-            },
-            stack_pointer_low_end,
-        )
+        ExceptionContext {
+            gpr: [0; 30],
+            lr: thread_return as *const () as u64,
+            elr_el1: entry_point,
+            spsr_el1: spsr_el1_init,
+            esr_el1: 0,
+            sp_el0: sp_value,
+            tpidr_el1: 0,
+        }
     }
 }
 
-impl Drop for Thread {
-    fn drop(&mut self) {
-        //info!("Deallocating thread {}", self.pid);
-        unsafe {
-            kernel_heap_allocator().dealloc(
-                self.original_stack as *mut u8,
-                Layout::from_size_align(STACK_SIZE, STACK_ALIGN).unwrap(),
-            )
+impl ExitStatus {
+    fn new() -> Self {
+        Self {
+            code: AtomicU64::new(0),
+            done: AtomicBool::new(false),
+            joiners: WaitQueue::new(),
         }
     }
+
+    fn finish(&self, code: u64) {
+        self.code.store(code, Ordering::Relaxed);
+        self.done.store(true, Ordering::Release);
+
+        self.joiners.notify_all();
+    }
+
+    fn is_done(&self) -> bool {
+        self.done.load(Ordering::Acquire)
+    }
+}
+
+impl Builder {
+    /// Start with a nameless thread of normal priority, placed on the least loaded core.
+    pub fn new() -> Self {
+        Self {
+            name: String::new(),
+            core: None,
+            priority: Priority::NORMAL,
+            stack_size: DEFAULT_STACK_SIZE,
+        }
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = String::from(name);
+        self
+    }
+
+    /// Queue the thread on `core` instead of the least loaded one.
+    pub fn core(mut self, core: usize) -> Self {
+        self.core = Some(core);
+        self
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Size of the stack in bytes, rounded up to whole 64 KiB pages.
+    ///
+    /// Every page of stack takes a page of the kernel heap, see `DEFAULT_STACK_SIZE`.
+    pub fn stack_size(mut self, stack_size: usize) -> Self {
+        self.stack_size = stack_size;
+        self
+    }
+
+    /// Spawn a thread running `f`.
+    pub fn spawn<F>(self, f: F) -> JoinHandle
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // Box twice, so that the trampoline receives a thin pointer.
+        let main: Box<ThreadMain> = Box::new(Box::new(f));
+        let entry_point = closure_trampoline as *const () as u64;
+
+        let mut new_thread = Thread::new_with_stack(
+            entry_point,
+            Box::into_raw(main) as u64,
+            self.priority,
+            self.stack_size,
+        );
+        new_thread.set_name(&self.name);
+        new_thread.set_core(
+            self.core
+                .unwrap_or_else(|| least_loaded_core(Affinity::ALL)),
+        );
+
+        let handle = new_thread.join_handle();
+        stats::register(new_thread.name(), new_thread.stats().clone());
+        enqueue(ThreadNode::new(new_thread));
+
+        handle
+    }
+}
+
+impl JoinHandle {
+    pub fn pid(&self) -> u64 {
+        self.pid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether the thread has ended already.
+    pub fn is_finished(&self) -> bool {
+        self.status.is_done()
+    }
+
+    /// Block until the thread has ended and return its exit code.
+    ///
+    /// Must be called from a thread.
+    pub fn join(self) -> u64 {
+        while !self.status.is_done() {
+            self.status.joiners.wait_if(|| !self.status.is_done());
+        }
+
+        self.status.code.load(Ordering::Relaxed)
+    }
 }
 
 impl fmt::Display for Thread {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "PID={}", self.pid)
+        write!(
+            f,
+            "PID={} NAME={} PRIO={} LEVEL={}",
+            self.pid,
+            self.name(),
+            self.priority,
+            self.level
+        )?;
+
+        if let Some(rt) = &self.rt {
+            write!(
+                f,
+                " RT(deadline={:?} overruns={} misses={})",
+                rt.abs_deadline(),
+                rt.overruns(),
+                rt.deadline_misses()
+            )?;
+        }
+
+        Ok(())
     }
 }
 extern "C" {
     pub fn __switch_to(current: &mut ExceptionContext, next: &mut ExceptionContext);
 }
 
+/// Return address of every thread's entry function.
+extern "C" fn thread_return() -> ! {
+    exit(0)
+}
+
+/// Entry point of threads created through `spawn()`. Runs the boxed closure and exits.
+extern "C" fn closure_trampoline(main: *mut ThreadMain) -> ! {
+    let main = unsafe { Box::from_raw(main) };
+    main();
+
+    exit(0)
+}
+
+/// Set the IRQ mask bit in the saved SPSR of a thread that switches away on its own.
+///
+/// The bit is set if IRQs are unmasked right now and cleared if they are masked. Callers hold the
+/// `CURRENT` lock, which masks IRQs, so in effect the thread always resumes with IRQs unmasked.
+fn save_irq_mask(thread: &mut Thread) {
+    let int_not_masked = !is_local_irq_masked();
+    //TODO: give abstraction to SPSR_EL1
+    if int_not_masked {
+        thread.get_ex_context().spsr_el1 |= 0x80;
+    } else {
+        thread.get_ex_context().spsr_el1 &= 0b11111111111111111111111101111111;
+    }
+}
+
 pub fn print_t() {
-    time_manager().spin_for(Duration::from_secs(10));
+    sleep_for(Duration::from_secs(10));
+
+    dump_all();
+    info!("Migrations: {}", balance::migrations())
+}
+
+/// Print a `ps`-style listing of all threads, followed by the utilization of every core.
+///
+/// The idle time of a core is the CPU time of the idle thread pinned to it.
+pub fn dump_all() {
+    let now = time_manager().uptime();
+    let mut idle = [Duration::ZERO; NR_CPUS];
+
+    info!(
+        "{:>5} {:<16} {:<8} {:>4} {:>12} {:>8} {:>8}",
+        "PID", "NAME", "STATE", "CORE", "RUNTIME(ms)", "VOLUNT", "INVOLUNT"
+    );
+    for (name, stats) in stats::registered() {
+        let runtime = stats.runtime(now);
+        if stats.is_idle() {
+            idle[stats.last_core()] += runtime;
+        }
 
-    for i in 0..=3 {
-        info!("RUNNING[{}]\n{}", i, RUNNING[i]);
+        info!(
+            "{:>5} {:<16} {:<8} {:>4} {:>12} {:>8} {:>8}",
+            stats.pid(),
+            name,
+            stats.state(),
+            stats.last_core(),
+            runtime.as_millis(),
+            stats.voluntary_switches(),
+            stats.involuntary_switches()
+        );
     }
 
-    info!("SLEEPING:\n{}", SLEEPING)
+    let uptime = now.as_nanos().max(1);
+    for (core, idle) in idle.iter().enumerate() {
+        let busy_permille = now.saturating_sub(*idle).as_nanos() * 1000 / uptime;
+        info!(
+            "Core{}: {}.{}% busy, idle for {} of {} ms, {} context switches",
+            core,
+            busy_permille / 10,
+            busy_permille % 10,
+            idle.as_millis(),
+            now.as_millis(),
+            stats::context_switches(core)
+        );
+    }
 }
 
 pub fn thread() {
@@ -122,7 +647,7 @@ pub fn thread() {
     //let stop_me: u64 = (random::next_u64() % 200) + 1;
     loop {
         let core: usize = core_id();
-        let my_pid = CURRENT[core].lock(|c| c);
+        let my_pid = current_pid();
         info!(
             "Hello from thread with PID={}! C={} @Core{}",
             my_pid.unwrap(),
@@ -141,56 +666,437 @@ pub fn thread() {
             );
             sleep();
         } */
-        reschedule();
-        time_manager().spin_for(Duration::from_millis((random::next_u64() % 2000) + 500));
+        sleep_for(Duration::from_millis((random::next_u64() % 2000) + 500));
     }
 }
 
+/// Body of the idle threads.
+///
+/// New work can only arrive through an interrupt, so the core sleeps until the next one.
 pub fn wait_thread() {
-    wait_forever();
+    loop {
+        wait_for_interrupt();
+    }
 }
 
+/// Spawn a thread running `f` on the least loaded core.
+pub fn spawn<F>(name: &str, f: F) -> JoinHandle
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with(None, Priority::NORMAL, name, f)
+}
+
+/// Spawn a thread running `f` on the given core.
+pub fn spawn_on<F>(core: usize, name: &str, f: F) -> JoinHandle
+where
+    F: FnOnce() + Send + 'static,
+{
+    spawn_with(Some(core), Priority::NORMAL, name, f)
+}
+
+/// Spawn a thread running `f` with the given priority.
+///
+/// The thread is queued on `core`, or on the least loaded core if `core` is `None`.
+pub fn spawn_with<F>(core: Option<usize>, priority: Priority, name: &str, f: F) -> JoinHandle
+where
+    F: FnOnce() + Send + 'static,
+{
+    let builder = Builder::new().name(name).priority(priority);
+
+    match core {
+        Some(core) => builder.core(core),
+        None => builder,
+    }
+    .spawn(f)
+}
+
+/// Park the current thread in `queue` if `condition` holds, and run the next thread meanwhile.
+///
+/// `condition` is evaluated with `queue` locked. A waker that invalidates the condition before
+/// calling `wake_one()`/`wake_all()` on the same queue therefore cannot be missed. Returns once the
+/// thread has been woken up, or right away if `condition` was false.
+pub fn block_on_if(queue: &ThreadQueue, condition: impl FnOnce() -> bool) {
+    let core: usize = core_id();
+    assert!(
+        !preempt::is_disabled(core),
+        "Thread blocks with preemption disabled on Core{}",
+        core
+    );
+
+    CURRENT[core].lock(|cur| {
+        let pid = cur.unwrap();
+
+        let ctx = queue.with_threads(|waiting| {
+            if !condition() {
+                return None;
+            }
+
+            let mut _my_thread = detach_runnable(core, pid)
+                .unwrap_or_else(|| panic!("Cannot find PID={} in RUNNING[{}]", pid, core));
+            _my_thread.set_core(core);
+            save_irq_mask(&mut _my_thread);
+            if !_my_thread.is_realtime() {
+                scheduling_policy().thread_yielded(&mut _my_thread);
+            }
+
+            let state = if core::ptr::eq(queue, &SLEEPING) {
+                ThreadState::Sleeping
+            } else {
+                ThreadState::Blocked
+            };
+            _my_thread
+                .stats()
+                .switched_out(time_manager().uptime(), state, true);
+            _my_thread.save_fp();
+
+            let ctx: *mut ExceptionContext = _my_thread.get_ex_context();
+            _my_thread.append_to(waiting);
+
+            Some(ctx)
+        });
+
+        if let Some(ctx) = ctx {
+            let (next_pid, next_ctx) = pick_next(core, *cur);
+            //debug!("[BLOCK] Switching to thread {}...", next_pid);
+            *cur = Some(next_pid);
+            unsafe { __switch_to(&mut *ctx, &mut *next_ctx) }
+        }
+    });
+}
+
+/// Wake the thread that has been waiting in `queue` the longest. Returns false if there was none.
+pub fn wake_one(queue: &ThreadQueue) -> bool {
+    match queue.detach_front() {
+        Some(node) => {
+            enqueue(node);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Wake all threads waiting in `queue` and return how many there were.
+pub fn wake_all(queue: &ThreadQueue) -> usize {
+    let mut woken = 0;
+    while wake_one(queue) {
+        woken += 1;
+    }
+
+    woken
+}
+
+/// Wake the thread with the given PID if it is waiting in `queue`.
+pub fn wake(queue: &ThreadQueue, pid: u64) -> bool {
+    queue.detach(pid).map(enqueue).is_some()
+}
+
+/// Park the current thread in `SLEEPING`.
 pub fn sleep() {
+    block_on_if(&SLEEPING, || true);
+}
+
+/// Park the current thread for at least `duration`, without using the CPU meanwhile.
+pub fn sleep_for(duration: Duration) {
+    sleep_until(time_manager().uptime() + duration);
+}
+
+/// Park the current thread until the uptime has reached `deadline`.
+///
+/// A one-shot timeout puts the thread back on the run queue of its core.
+pub fn sleep_until(deadline: Duration) {
+    let now = time_manager().uptime();
+    if deadline <= now {
+        return;
+    }
+
+    let pid = current_pid().expect("sleep_until() must be called from a thread");
+    let sleeps = SLEEPS.with(Arc::clone);
+    let sleep = sleeps.load(Ordering::Relaxed);
+
+    let timer_sleeps = sleeps.clone();
+    let timeout = time_manager().set_timeout_once(
+        deadline - now,
+        Box::new(move |_| {
+            if timer_sleeps
+                .compare_exchange(sleep, sleep + 1, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                wake(&SLEEPING, pid);
+            }
+        }),
+    );
+
+    // Checked with SLEEPING locked, so a timeout that fires before the thread is parked is not
+    // lost.
+    block_on_if(&SLEEPING, || sleeps.load(Ordering::Acquire) == sleep);
+
+    // The thread might have been woken before the deadline, e.g. by `wake()`. Ending the sleep
+    // keeps a timeout that can no longer be cancelled from waking the thread during a later one.
+    timeout.cancel();
+    sleeps.store(sleep + 1, Ordering::Relaxed);
+}
+
+/// Let the thread running on the executing core use FP/SIMD from now on.
+///
+/// Called from the trap taken on its first FP/SIMD instruction. The thread starts out with all
+/// FP/SIMD registers zeroed.
+pub fn enable_fp() {
     let core: usize = core_id();
 
     CURRENT[core].lock(|cur| {
-        let mut _my_thread = RUNNING[core].remove(cur.unwrap()).unwrap();
-        let next_thread = RUNNING[core].next().expect("No next thread found!");
-        //debug!("[SLEEP] Switching to thread {}...", next_thread.get_pid());
-        let int_not_masked = !is_local_irq_masked();
-        //TODO: give abstraction to SPSR_EL1
-        if int_not_masked {
-            _my_thread.get_ex_context().spsr_el1 |= 0x80;
-        } else {
-            _my_thread.get_ex_context().spsr_el1 &= 0b11111111111111111111111101111111;
-        }
-        SLEEPING.add(_my_thread);
-        let _my_thread = SLEEPING
-            .get_by_pid(cur.unwrap())
-            .unwrap_or_else(|| panic!("Cannot find PID={} in SLEEPING[{}]", cur.unwrap(), core));
-        *cur = Some(next_thread.get_pid());
-        unsafe { __switch_to(_my_thread.get_ex_context(), next_thread.get_ex_context()) }
+        let pid = cur.expect("FP/SIMD trap outside of a thread");
+
+        with_runnable(core, pid, |_my_thread| {
+            _my_thread
+                .fp
+                .get_or_insert_with(|| Box::new(FpState::new()))
+                .restore();
+        })
+        .unwrap_or_else(|| panic!("Cannot find PID={} in RUNNING[{}]", pid, core));
     });
 }
 
+/// PID of the thread running on the executing core.
+pub fn current_pid() -> Option<u64> {
+    tls::current().map(TlsBlock::pid)
+}
+
+/// Give up the CPU, staying runnable.
 pub fn reschedule() {
+    switch_away(true);
+}
+
+/// Switch to the next thread as if the running thread had been preempted by the tick.
+///
+/// Used to carry out a switch that was held back while preemption was disabled.
+pub fn preempt() {
+    switch_away(false);
+}
+
+fn switch_away(voluntary: bool) {
+    let core: usize = core_id();
+    assert!(
+        !preempt::is_disabled(core),
+        "Thread switches away with preemption disabled on Core{}",
+        core
+    );
+
+    CURRENT[core].lock(|cur| {
+        let ctx = with_runnable(core, cur.unwrap(), |_my_thread| {
+            if !_my_thread.is_realtime() {
+                if voluntary {
+                    scheduling_policy().thread_yielded(_my_thread);
+                } else {
+                    scheduling_policy().thread_preempted(_my_thread);
+                }
+            }
+            _my_thread.stats().switched_out(
+                time_manager().uptime(),
+                ThreadState::Runnable,
+                voluntary,
+            );
+            _my_thread.save_fp();
+            save_irq_mask(_my_thread);
+
+            let ctx: *mut ExceptionContext = _my_thread.get_ex_context();
+            ctx
+        })
+        .unwrap_or_else(|| panic!("Cannot find PID={} in RUNNING[{}]", cur.unwrap(), core));
+
+        let (next_pid, next_ctx) = pick_next(core, *cur);
+        //debug!("[RESCHEDULE] Switching to thread {}...", next_pid);
+        *cur = Some(next_pid);
+        unsafe { __switch_to(&mut *ctx, &mut *next_ctx) }
+    });
+}
+
+/// Terminate the calling thread with the given exit code.
+///
+/// Threads whose entry function returns end up here with exit code 0. Joiners are woken up, and
+/// the stack is freed by the next context switch on this core, once nothing runs on it anymore.
+pub fn exit(code: u64) -> ! {
     let core: usize = core_id();
 
-    let next_thread = RUNNING[core].next().expect("No next thread found!");
-    //debug!("[RESCHEDULE] Switching to thread {}...", next_thread.get_pid());
+    CURRENT[core].lock(|cur| {
+        let pid = cur.unwrap();
+        let mut _my_thread = detach_runnable(core, pid)
+            .unwrap_or_else(|| panic!("Cannot find PID={} in RUNNING[{}]", pid, core));
+        assert!(
+            !_my_thread.is_idle(),
+            "Idle thread PID={} must not exit",
+            pid
+        );
+
+        _my_thread.exit_status.finish(code);
+        stats::unregister(pid);
+        edf::release(&_my_thread);
+
+        let (next_pid, next_ctx) = pick_next(core, *cur);
+        *cur = Some(next_pid);
+
+        let ctx: *mut ExceptionContext = _my_thread.get_ex_context();
+        bury(core, _my_thread);
+        unsafe { __switch_to(&mut *ctx, &mut *next_ctx) }
+    });
+
+    unreachable!("Exited thread got scheduled again");
+}
+
+/// End the current job of a real-time thread and give up the CPU until its next release.
+///
+/// Has the same effect as `reschedule()` when called from a normal thread.
+pub fn wait_next_period() {
+    let core: usize = core_id();
 
     CURRENT[core].lock(|cur| {
-        let _my_thread = RUNNING[core]
-            .get_by_pid(cur.unwrap())
-            .unwrap_or_else(|| panic!("Cannot find PID={} in RUNNING[{}]", cur.unwrap(), core));
-        let int_not_masked = !is_local_irq_masked();
-        //TODO: give abstraction to SPSR_EL1
-        if int_not_masked {
-            _my_thread.get_ex_context().spsr_el1 |= 0x80;
-        } else {
-            _my_thread.get_ex_context().spsr_el1 &= 0b11111111111111111111111101111111;
-        }
-        *cur = Some(next_thread.get_pid());
-        unsafe { __switch_to(_my_thread.get_ex_context(), next_thread.get_ex_context()) }
+        RT_RUNNING[core].with_pid(cur.unwrap(), |t| {
+            if let Some(rt) = t.realtime_mut() {
+                rt.complete_job();
+            }
+        });
     });
+
+    reschedule();
+}
+
+/// Run `f` on the thread with the given PID if it is runnable or sleeping.
+///
+/// Also returns the core whose run queue holds the thread, which is `None` while it sleeps.
+///
+/// The PID index turns away unknown PIDs without taking a lock and tells which queue most likely
+/// holds the thread, so that it is searched first.
+fn with_thread<R>(pid: u64, f: impl FnOnce(&mut Thread) -> R) -> Option<(Option<usize>, R)> {
+    let stats = stats::lookup(pid)?;
+    let likely = match stats.state() {
+        ThreadState::Sleeping => RUNNING.len(),
+        _ => stats.last_core(),
+    };
+    let mut f = Some(f);
+
+    core::iter::once(likely)
+        .chain((0..=RUNNING.len()).filter(|&i| i != likely))
+        .find_map(|i| {
+            let queue = if i < RUNNING.len() {
+                &RUNNING[i]
+            } else {
+                &SLEEPING
+            };
+
+            queue.with_threads(|threads| {
+                let t = threads.iter_mut().find(|t| t.get_pid() == pid)?;
+                let owner = (i < RUNNING.len()).then_some(i);
+
+                Some((owner, (f.take().unwrap())(t)))
+            })
+        })
+}
+
+/// Move the thread with the given PID to `core`.
+///
+/// The thread may be running. The core owning it is interrupted and hands it over once its context
+/// has been saved, so the move has not necessarily happened when this returns. A sleeping thread
+/// moves on its next wakeup. Real-time threads stay on the core that admitted them.
+pub fn migrate(pid: u64, core: usize) -> Result<(), &'static str> {
+    if core >= RUNNING.len() {
+        return Err("No such core");
+    }
+    if smp::is_offline(core) {
+        return Err("Core is offline");
+    }
+
+    let (owner, requested) = with_thread(pid, |t| t.request_migration(core))
+        .ok_or("No runnable or sleeping thread with this PID")?;
+    requested?;
+
+    if let Some(owner) = owner {
+        kick(owner);
+    }
+
+    Ok(())
+}
+
+/// Restrict the thread with the given PID to the cores in `affinity`.
+///
+/// If the thread is on a core outside of `affinity`, it is migrated to the least loaded allowed
+/// core.
+pub fn set_affinity(pid: u64, affinity: Affinity) -> Result<(), &'static str> {
+    let (_, core) = with_thread(pid, |t| {
+        t.set_affinity(affinity)?;
+        Ok::<_, &'static str>(t.core())
+    })
+    .ok_or("No runnable or sleeping thread with this PID")?;
+
+    if affinity.contains(core?) {
+        return Ok(());
+    }
+
+    migrate(pid, least_loaded_core(affinity))
+}
+
+/// Change the static priority of the thread with the given PID if it is runnable or sleeping.
+///
+/// Real-time threads are scheduled by their deadlines and have no priority, so they are rejected.
+pub fn set_priority(pid: u64, priority: Priority) -> Result<(), &'static str> {
+    const REALTIME: &str = "Real-time threads have no priority";
+
+    if RT_RUNNING
+        .iter()
+        .any(|queue| queue.with_threads(|threads| threads.iter().any(|t| t.get_pid() == pid)))
+    {
+        return Err(REALTIME);
+    }
+
+    let (_, result) = with_thread(pid, |t| {
+        if t.is_realtime() {
+            return Err(REALTIME);
+        }
+
+        t.set_priority(priority);
+        Ok(())
+    })
+    .ok_or("No runnable or sleeping thread with this PID")?;
+
+    result
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Check that `Priority::new` keeps values in range and saturates above `Priority::HIGH`.
+    #[kernel_test]
+    fn priority_new_saturates_at_high() {
+        assert_eq!(Priority::new(0), Priority::IDLE);
+        assert_eq!(Priority::new(2), Priority::NORMAL);
+        assert_eq!(Priority::new(Priority::HIGH.get()), Priority::HIGH);
+        assert_eq!(Priority::new(Priority::HIGH.get() + 1), Priority::HIGH);
+        assert_eq!(Priority::new(u8::MAX), Priority::HIGH);
+    }
+
+    /// Check that `Affinity::ALL` covers exactly the `NR_CPUS` cores.
+    #[kernel_test]
+    fn affinity_all_matches_nr_cpus() {
+        assert_eq!(Affinity::ALL.bits().count_ones() as usize, NR_CPUS);
+        assert!((0..NR_CPUS).all(|core| Affinity::ALL.contains(core)));
+        assert!(!Affinity::ALL.contains(NR_CPUS));
+        assert!(Affinity::ALL.cores().eq(0..NR_CPUS));
+    }
+
+    /// Check the set operations of `Affinity`.
+    #[kernel_test]
+    fn affinity_bit_ops() {
+        assert_eq!(Affinity::single(2).bits(), 0b100);
+        assert_eq!(Affinity::from_cores([0, 2]).bits(), 0b101);
+        assert_eq!(Affinity::from_cores([1, NR_CPUS]), Affinity::single(1));
+        assert_eq!(Affinity::from_bits(u8::MAX), Affinity::ALL);
+        assert!(Affinity::from_bits(0).is_empty());
+        assert!(Affinity::from_cores([0, 2]).cores().eq([0, 2]));
+        assert!(!Affinity::single(0).contains(u8::BITS as usize));
+    }
 }
@@ -0,0 +1,19 @@
+//! Lazy FP/SIMD context switching.
+//!
+//! The kernel itself is built without FP/SIMD, so V0-V31, FPCR and FPSR only ever hold thread
+//! state. FP/SIMD instructions trap until a thread executes its first one. The trap gives the
+//! thread a save area and lets it continue with FP/SIMD enabled. From then on, its registers are
+//! saved when it is switched out and loaded again when it is switched in. Threads that never
+//! touched FP/SIMD cost a single CPACR_EL1 write per switch.
+//!
+//! All of this only happens in builds with the `fp` feature (`make FP=1`). Other builds neither
+//! handle the trap nor touch FP/SIMD state on a switch.
+//!
+//! Code that wants to use NEON opts in per function through `#[target_feature(enable = "neon")]`.
+//! Such functions must not take or return floating point values by value, since the rest of the
+//! kernel passes them in general purpose registers.
+
+#[path = "../aarch64/fp.rs"]
+mod arch_fp;
+
+pub use arch_fp::{disable, FpState};
@@ -0,0 +1,264 @@
+//! Per-thread CPU time accounting.
+//!
+//! Every thread shares its `ThreadStats` with a global registry, so that threads can be listed no
+//! matter which queue they are parked in, private wait queues included. The registry is indexed by
+//! PID and protected by RCU, so listing or looking up threads never takes a lock and never holds up
+//! spawning or exiting ones. The scheduler updates the
+//! counters whenever it switches a thread in or out.
+//!
+//! A switch only counts once another thread actually took over the CPU. A preempted or yielding
+//! thread that gets picked again right away has not been switched out.
+
+use crate::{
+    per_cpu,
+    synchronization::{interface::ReadWriteEx, rcu::Rcu, IRQSafeSeqLock},
+};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Kind of the last switch out, until the thread runs again.
+const SWITCH_NONE: u8 = 0;
+const SWITCH_VOLUNTARY: u8 = 1;
+const SWITCH_INVOLUNTARY: u8 = 2;
+
+/// CPU time of a thread, updated and read as one snapshot.
+#[derive(Copy, Clone)]
+struct CpuTime {
+    /// Nanoseconds, without the slice in progress.
+    runtime: u64,
+    /// Uptime in nanoseconds at which the slice in progress started, if the thread is running.
+    slice_start: Option<u64>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    name: String,
+    stats: Arc<ThreadStats>,
+}
+
+static REGISTRY: Rcu<BTreeMap<u64, Entry>> = Rcu::empty();
+
+per_cpu! {
+    /// Switches to a different thread made by each core.
+    static CONTEXT_SWITCHES: AtomicU64 = AtomicU64::new(0);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Scheduling state of a thread.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ThreadState {
+    Running,
+    Runnable,
+    /// Parked in `SLEEPING`.
+    Sleeping,
+    /// Parked in any other wait queue.
+    Blocked,
+}
+
+/// Accounting data of a single thread.
+pub struct ThreadStats {
+    pid: u64,
+    /// Set for the idle threads, which never leave their core.
+    idle: bool,
+    state: AtomicU8,
+    cpu_time: IRQSafeSeqLock<CpuTime>,
+    last_core: AtomicUsize,
+    voluntary_switches: AtomicU64,
+    involuntary_switches: AtomicU64,
+    pending_switch: AtomicU8,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl ThreadState {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Running,
+            1 => Self::Runnable,
+            2 => Self::Sleeping,
+            _ => Self::Blocked,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for ThreadState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Running => "running",
+            Self::Runnable => "runnable",
+            Self::Sleeping => "sleeping",
+            Self::Blocked => "blocked",
+        };
+
+        f.pad(name)
+    }
+}
+
+impl ThreadStats {
+    /// Create an instance for a thread that has not run yet.
+    pub const fn new(pid: u64, idle: bool) -> Self {
+        Self {
+            pid,
+            idle,
+            state: AtomicU8::new(ThreadState::Runnable as u8),
+            cpu_time: IRQSafeSeqLock::new(CpuTime {
+                runtime: 0,
+                slice_start: None,
+            }),
+            last_core: AtomicUsize::new(0),
+            voluntary_switches: AtomicU64::new(0),
+            involuntary_switches: AtomicU64::new(0),
+            pending_switch: AtomicU8::new(SWITCH_NONE),
+        }
+    }
+
+    pub fn pid(&self) -> u64 {
+        self.pid
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    pub fn state(&self) -> ThreadState {
+        ThreadState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+
+    pub fn set_state(&self, state: ThreadState) {
+        self.state.store(state as u8, Ordering::Relaxed);
+    }
+
+    /// CPU time used up to `now`, including the slice in progress if the thread is running.
+    pub fn runtime(&self, now: Duration) -> Duration {
+        let cpu_time = self.cpu_time.get();
+        let running = cpu_time
+            .slice_start
+            .map_or(0, |start| (now.as_nanos() as u64).saturating_sub(start));
+
+        Duration::from_nanos(cpu_time.runtime + running)
+    }
+
+    /// Core the thread ran on most recently.
+    pub fn last_core(&self) -> usize {
+        self.last_core.load(Ordering::Relaxed)
+    }
+
+    /// Number of times the thread gave up the CPU by blocking, sleeping or yielding.
+    pub fn voluntary_switches(&self) -> u64 {
+        self.voluntary_switches.load(Ordering::Relaxed)
+    }
+
+    /// Number of times the thread was preempted.
+    pub fn involuntary_switches(&self) -> u64 {
+        self.involuntary_switches.load(Ordering::Relaxed)
+    }
+
+    /// The thread starts running on `core` at `now`.
+    ///
+    /// `resumed` is set if it was the thread that ran on `core` before, in which case its last
+    /// switch out did not happen after all.
+    pub fn switched_in(&self, core: usize, now: Duration, resumed: bool) {
+        if !resumed {
+            CONTEXT_SWITCHES[core].fetch_add(1, Ordering::Relaxed);
+        }
+
+        match self.pending_switch.swap(SWITCH_NONE, Ordering::Relaxed) {
+            SWITCH_VOLUNTARY if !resumed => {
+                self.voluntary_switches.fetch_add(1, Ordering::Relaxed);
+            }
+            SWITCH_INVOLUNTARY if !resumed => {
+                self.involuntary_switches.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => (),
+        }
+
+        self.last_core.store(core, Ordering::Relaxed);
+        self.cpu_time
+            .write(|cpu_time| cpu_time.slice_start = Some(now.as_nanos() as u64));
+        self.set_state(ThreadState::Running);
+    }
+
+    /// The thread stops running at `now` and moves to `state`.
+    pub fn switched_out(&self, now: Duration, state: ThreadState, voluntary: bool) {
+        self.cpu_time.write(|cpu_time| {
+            if let Some(start) = cpu_time.slice_start.take() {
+                cpu_time.runtime += (now.as_nanos() as u64).saturating_sub(start);
+            }
+        });
+
+        self.pending_switch.store(
+            if voluntary {
+                SWITCH_VOLUNTARY
+            } else {
+                SWITCH_INVOLUNTARY
+            },
+            Ordering::Relaxed,
+        );
+        self.set_state(state);
+    }
+}
+
+/// Number of times `core` switched to a different thread.
+pub fn context_switches(core: usize) -> u64 {
+    CONTEXT_SWITCHES[core].load(Ordering::Relaxed)
+}
+
+/// Make a thread show up in `registered()`.
+pub fn register(name: &str, stats: Arc<ThreadStats>) {
+    REGISTRY.update(|entries| {
+        let mut entries = entries.cloned().unwrap_or_default();
+        entries.insert(
+            stats.pid(),
+            Entry {
+                name: String::from(name),
+                stats,
+            },
+        );
+
+        entries
+    })
+}
+
+/// Remove the thread with the given PID from the registry.
+pub fn unregister(pid: u64) {
+    REGISTRY.update(|entries| {
+        let mut entries = entries.cloned().unwrap_or_default();
+        entries.remove(&pid);
+
+        entries
+    })
+}
+
+/// Return the name and accounting data of every registered thread, ordered by PID.
+pub fn registered() -> Vec<(String, Arc<ThreadStats>)> {
+    REGISTRY.read(|entries| {
+        entries
+            .into_iter()
+            .flat_map(BTreeMap::values)
+            .map(|e| (e.name.clone(), e.stats.clone()))
+            .collect()
+    })
+}
+
+/// Return the accounting data of the thread with the given PID, if it is registered.
+pub fn lookup(pid: u64) -> Option<Arc<ThreadStats>> {
+    REGISTRY.read(|entries| entries?.get(&pid).map(|e| e.stats.clone()))
+}
@@ -0,0 +1,232 @@
+//! Kernel thread-local storage.
+//!
+//! Every thread owns a `TlsBlock`, allocated together with the thread. While the thread runs,
+//! TPIDR_EL1 points to it. The register is part of the saved `ExceptionContext`, so exception
+//! entry/exit and `__switch_to` keep it in sync with the running thread. As long as no thread has
+//! run on a core, its TPIDR_EL1 is zero.
+//!
+//! Values declared through `thread_local!` live in the slots of the block. Each declaration is
+//! assigned a slot on first use, and its value is created lazily for every thread that accesses it.
+//! IRQ handlers see the block of the thread they interrupted.
+
+use crate::{
+    exception::asynchronous::{local_irq_mask_save, local_irq_restore},
+    memory::{Address, Virtual},
+};
+use aarch64_cpu::registers::TPIDR_EL1;
+use alloc::{boxed::Box, string::String};
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tock_registers::interfaces::{Readable, Writeable};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of `thread_local!` declarations the kernel can hold.
+const TLS_SLOTS: usize = 32;
+
+/// Marks a `LocalKey` whose slot has not been assigned yet.
+const UNASSIGNED: usize = usize::MAX;
+
+static NEXT_SLOT: AtomicUsize = AtomicUsize::new(0);
+
+/// Initial content of every slot.
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: Option<Slot> = None;
+
+type Slots = [Option<Slot>; TLS_SLOTS];
+
+/// A thread-local value together with the function that frees it.
+struct Slot {
+    value: *mut u8,
+    drop: unsafe fn(*mut u8),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Thread-local storage of a single thread.
+pub struct TlsBlock {
+    pid: u64,
+    name: String,
+    stack_bottom: Address<Virtual>,
+    slots: UnsafeCell<Slots>,
+}
+
+/// Key to a thread-local value, declared through `thread_local!`.
+pub struct LocalKey<T: 'static> {
+    slot: AtomicUsize,
+    init: fn() -> T,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+unsafe fn drop_boxed<T>(value: *mut u8) {
+    drop(Box::from_raw(value as *mut T));
+}
+
+impl TlsBlock {
+    /// Run `f` on the slots with local IRQs masked, so that a handler using the same key on the
+    /// interrupted thread's block cannot interfere.
+    fn with_slots<R>(&self, f: impl FnOnce(&mut Slots) -> R) -> R {
+        let saved = local_irq_mask_save();
+        let ret = f(unsafe { &mut *self.slots.get() });
+        local_irq_restore(saved);
+
+        ret
+    }
+
+    fn get(&self, slot: usize) -> Option<*mut u8> {
+        self.with_slots(|slots: &mut Slots| slots[slot].as_ref().map(|s| s.value))
+    }
+
+    /// Store `value` in an empty slot and return it. If a handler on the same thread filled the
+    /// slot in the meantime, `value` is freed and the existing value returned instead.
+    fn get_or_insert(&self, slot: usize, value: *mut u8, drop: unsafe fn(*mut u8)) -> *mut u8 {
+        self.with_slots(|slots: &mut Slots| match &slots[slot] {
+            Some(s) => {
+                unsafe { drop(value) };
+                s.value
+            }
+            None => {
+                slots[slot] = Some(Slot { value, drop });
+                value
+            }
+        })
+    }
+}
+
+impl<T: 'static> LocalKey<T> {
+    fn slot(&self) -> usize {
+        let slot = self.slot.load(Ordering::Acquire);
+        if slot != UNASSIGNED {
+            return slot;
+        }
+
+        let new = NEXT_SLOT.fetch_add(1, Ordering::Relaxed);
+        assert!(new < TLS_SLOTS, "Out of thread-local storage slots");
+
+        // Another core may have won the race. Its slot is used then, and `new` stays unused.
+        match self
+            .slot
+            .compare_exchange(UNASSIGNED, new, Ordering::AcqRel, Ordering::Acquire)
+        {
+            Ok(_) => new,
+            Err(other) => other,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+// The raw pointers in the slots are owned by the block and only accessed by the thread owning it.
+unsafe impl Send for TlsBlock {}
+
+impl TlsBlock {
+    /// Create the block of the thread with the given PID, whose stack starts at `stack_bottom`.
+    pub fn new(pid: u64, stack_bottom: Address<Virtual>) -> Box<Self> {
+        Box::new(Self {
+            pid,
+            name: String::new(),
+            stack_bottom,
+            slots: UnsafeCell::new([EMPTY_SLOT; TLS_SLOTS]),
+        })
+    }
+
+    pub fn pid(&self) -> u64 {
+        self.pid
+    }
+
+    /// Name of the thread. Kept here, so that it can be read without taking any lock.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Lowest address of the thread's stack. Its guard page lies right below.
+    pub fn stack_bottom(&self) -> Address<Virtual> {
+        self.stack_bottom
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+}
+
+impl Drop for TlsBlock {
+    /// Free all values created by the thread. Runs after the thread ended, so the destructors must
+    /// not use thread-local storage themselves.
+    fn drop(&mut self) {
+        for slot in self.slots.get_mut().iter_mut().filter_map(Option::take) {
+            unsafe { (slot.drop)(slot.value) }
+        }
+    }
+}
+
+impl<T: 'static> LocalKey<T> {
+    #[doc(hidden)]
+    pub const fn new(init: fn() -> T) -> Self {
+        Self {
+            slot: AtomicUsize::new(UNASSIGNED),
+            init,
+        }
+    }
+
+    /// Grant the closure access to the running thread's value, creating the value first if needed.
+    ///
+    /// Panics if no thread has run on the executing core yet.
+    pub fn with<R>(&'static self, f: impl FnOnce(&T) -> R) -> R {
+        let block = current().expect("Thread-local storage used outside of a thread");
+        let slot = self.slot();
+
+        let value = block.get(slot).unwrap_or_else(|| {
+            // Not created with IRQs masked, as the initializer may take arbitrarily long.
+            let new = Box::into_raw(Box::new((self.init)())) as *mut u8;
+
+            block.get_or_insert(slot, new, drop_boxed::<T>)
+        });
+
+        f(unsafe { &*(value as *const T) })
+    }
+}
+
+/// The TLS block of the thread running on the executing core, if any.
+pub fn current() -> Option<&'static TlsBlock> {
+    unsafe { (TPIDR_EL1.get() as *const TlsBlock).as_ref() }
+}
+
+/// Clear the thread pointer of the executing core.
+///
+/// # Safety
+///
+/// - Must be called once per core during boot, before the first thread is scheduled there.
+pub unsafe fn init() {
+    TPIDR_EL1.set(0);
+}
+
+/// Declare kernel thread-local values, similar to `std::thread_local!`.
+///
+/// ```ignore
+/// thread_local! {
+///     static DEPTH: Cell<usize> = Cell::new(0);
+/// }
+///
+/// DEPTH.with(|depth| depth.set(depth.get() + 1));
+/// ```
+#[macro_export]
+macro_rules! thread_local {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty = $init:expr;)*) => {
+        $(
+            $(#[$attr])*
+            $vis static $name: $crate::thread::tls::LocalKey<$t> =
+                $crate::thread::tls::LocalKey::new(|| $init);
+        )*
+    };
+}
@@ -13,15 +13,29 @@
 mod arch_time;
 
 use crate::{
+    cpu::{core_id, per_cpu::PerCpu, BOOT_CORE_ID, NR_CPUS},
     driver, exception,
-    exception::{arch_exception::ExceptionContext, asynchronous::IRQNumber},
-    synchronization::{interface::Mutex, IRQSafeLock},
-    warn,
+    exception::{
+        arch_exception::ExceptionContext,
+        asynchronous::{is_local_irq_masked, IRQNumber},
+    },
+    scheduler::preempt,
+    smp,
+    synchronization::{interface::Mutex, IRQSafeLock, SpinLock},
 };
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BinaryHeap},
+    sync::Arc,
+    vec::Vec,
+};
 use core::{
-    sync::atomic::{AtomicBool, Ordering},
+    cmp::Reverse,
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
     time::Duration,
 };
 
@@ -36,10 +50,19 @@ pub fn time_manager() -> &'static TimeManager {
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// IDs of timeouts are unique across all cores, so that handles find them wherever they are.
+static NEXT_TIMEOUT_ID: AtomicU64 = AtomicU64::new(0);
+
 struct Timeout {
     due_time: Duration,
     period: Option<Duration>,
-    callback: TimeoutCallback,
+
+    /// Whether the timeout stays on its core while the core is offline, instead of moving to the
+    /// boot core.
+    pinned: bool,
+
+    /// Taken out while the callback runs.
+    callback: Option<TimeoutCallback>,
 }
 
 impl Timeout {
@@ -47,42 +70,159 @@ impl Timeout {
         self.period.is_some()
     }
 
-    pub fn refresh(&mut self) {
+    /// Move a periodic timeout on to its next due time after `now`.
+    ///
+    /// Periods that were missed entirely, e.g. while its core was offline, are skipped.
+    pub fn refresh(&mut self, now: Duration) {
         if let Some(delay) = self.period {
             self.due_time += delay;
+            if self.due_time <= now {
+                self.due_time = now + delay;
+            }
         }
     }
 }
 
+/// Pending timeouts, ordered by due time.
+///
+/// The timeouts live in `timeouts`, keyed by ID, while `due` orders their IDs by due time.
+/// Cancelling or modifying a timeout leaves its old entry in `due` behind, which is skipped once it
+/// comes up. Once stale entries outnumber the live ones, `due` is rebuilt from `timeouts`, so that
+/// it cannot grow without bounds. Every operation stays at amortized O(log n).
 struct OrderedTimeoutQueue {
-    // Can be replaced with a BinaryHeap once it's new() becomes const.
-    inner: Vec<Timeout>,
+    due: BinaryHeap<Reverse<(Duration, u64)>>,
+    timeouts: BTreeMap<u64, Timeout>,
 }
 
 impl OrderedTimeoutQueue {
     pub const fn new() -> Self {
-        Self { inner: Vec::new() }
+        Self {
+            due: BinaryHeap::new(),
+            timeouts: BTreeMap::new(),
+        }
+    }
+
+    /// Whether an entry of `due` still belongs to a pending timeout.
+    fn is_current(&self, due_time: Duration, id: u64) -> bool {
+        self.timeouts
+            .get(&id)
+            .is_some_and(|t| t.due_time == due_time && t.callback.is_some())
+    }
+
+    /// Rebuild `due` from the timeouts that are queued.
+    fn rebuild_due(&mut self) {
+        self.due = self
+            .timeouts
+            .iter()
+            .filter(|(_, timeout)| timeout.callback.is_some())
+            .map(|(&id, timeout)| Reverse((timeout.due_time, id)))
+            .collect();
+    }
+
+    /// Rebuild `due` if it holds more stale entries than live ones.
+    fn compact(&mut self) {
+        if self.due.len() > 2 * self.timeouts.len() {
+            self.rebuild_due();
+        }
+    }
+
+    fn discard_stale(&mut self) {
+        while let Some(&Reverse((due_time, id))) = self.due.peek() {
+            if self.is_current(due_time, id) {
+                break;
+            }
+            self.due.pop();
+        }
+    }
+
+    pub fn push(&mut self, id: u64, timeout: Timeout) {
+        self.due.push(Reverse((timeout.due_time, id)));
+        self.timeouts.insert(id, timeout);
+    }
+
+    pub fn peek_next_due_time(&mut self) -> Option<Duration> {
+        self.discard_stale();
+
+        self.due.peek().map(|Reverse((due_time, _))| *due_time)
+    }
+
+    /// Take the callback of the earliest timeout, if it is due at `now`.
+    ///
+    /// One-shot timeouts are removed. Periodic ones move on to their next due time, but are only
+    /// queued again once their callback is handed back with `put_back()`.
+    pub fn pop_due(&mut self, now: Duration) -> Option<(u64, TimeoutCallback)> {
+        if self.peek_next_due_time()? > now {
+            return None;
+        }
+
+        let Reverse((_, id)) = self.due.pop().unwrap();
+        let timeout = self.timeouts.get_mut(&id).unwrap();
+        if !timeout.is_periodic() {
+            return self.timeouts.remove(&id).unwrap().callback.map(|c| (id, c));
+        }
+
+        // Refresh as early as possible to prevent drift.
+        timeout.refresh(now);
+        timeout.callback.take().map(|c| (id, c))
+    }
+
+    /// Queue a periodic timeout again after its callback ran.
+    ///
+    /// The callback is dropped if the timeout was cancelled meanwhile, or was a one-shot timeout.
+    pub fn put_back(&mut self, id: u64, callback: TimeoutCallback) {
+        if let Some(timeout) = self.timeouts.get_mut(&id) {
+            timeout.callback = Some(callback);
+            self.due.push(Reverse((timeout.due_time, id)));
+        }
     }
 
-    pub fn push(&mut self, timeout: Timeout) {
-        self.inner.push(timeout);
+    /// Remove a timeout. Returns whether it was still pending.
+    pub fn cancel(&mut self, id: u64) -> bool {
+        let pending = self.timeouts.remove(&id).is_some();
+        self.compact();
 
-        // Note reverse compare order so that earliest expiring item is at end of vec. We do this so
-        // that we can use Vec::pop below to retrieve the item that is next due.
-        self.inner.sort_by(|a, b| b.due_time.cmp(&a.due_time));
+        pending
     }
 
-    pub fn peek_next_due_time(&self) -> Option<Duration> {
-        let timeout = self.inner.last()?;
+    /// Move a timeout to `due_time`. Returns whether it was still pending.
+    pub fn modify(&mut self, id: u64, due_time: Duration) -> bool {
+        let timeout = match self.timeouts.get_mut(&id) {
+            Some(timeout) => timeout,
+            None => return false,
+        };
 
-        Some(timeout.due_time)
+        timeout.due_time = due_time;
+        // A periodic timeout whose callback is running is queued by `put_back()`.
+        if timeout.callback.is_some() {
+            self.due.push(Reverse((due_time, id)));
+            self.compact();
+        }
+
+        true
     }
 
-    pub fn pop(&mut self) -> Option<Timeout> {
-        self.inner.pop()
+    /// Move all timeouts that are not pinned to `other`.
+    ///
+    /// No callback of this queue may be running.
+    pub fn move_unpinned_to(&mut self, other: &mut Self) {
+        let (pinned, unpinned) = core::mem::take(&mut self.timeouts)
+            .into_iter()
+            .partition(|(_, timeout)| timeout.pinned);
+        self.timeouts = pinned;
+        self.rebuild_due();
+
+        for (id, timeout) in unpinned {
+            other.push(id, timeout);
+        }
     }
 }
 
+/// State shared between a `Sleep` and its timeout.
+struct SleepState {
+    expired: AtomicBool,
+    waker: IRQSafeLock<SpinLock<Option<Waker>>>,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -91,8 +231,28 @@ impl OrderedTimeoutQueue {
 pub type TimeoutCallback = Box<dyn Fn(&mut ExceptionContext) + Send>;
 
 /// Provides time management functions.
+///
+/// Every core has its own timeout queue and programs its own timer for it, so a timeout fires on
+/// the core it was set on.
 pub struct TimeManager {
-    queue: IRQSafeLock<OrderedTimeoutQueue>,
+    queues: PerCpu<IRQSafeLock<OrderedTimeoutQueue>>,
+}
+
+/// Handle to a timeout, returned when setting it.
+///
+/// Dropping the handle leaves the timeout in place.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TimeoutHandle {
+    id: u64,
+}
+
+/// Future returned by `TimeManager::sleep()`.
+///
+/// Dropping it before it completed cancels its timeout.
+pub struct Sleep {
+    deadline: Duration,
+    state: Option<Arc<SleepState>>,
+    timeout: Option<TimeoutHandle>,
 }
 
 impl TimeManager {
@@ -102,7 +262,7 @@ impl TimeManager {
     /// Create an instance.
     pub const fn new() -> Self {
         Self {
-            queue: IRQSafeLock::new(OrderedTimeoutQueue::new()),
+            queues: PerCpu::new([const { IRQSafeLock::new(OrderedTimeoutQueue::new()) }; NR_CPUS]),
         }
     }
 
@@ -123,35 +283,235 @@ impl TimeManager {
         arch_time::spin_for(duration)
     }
 
-    /// Set a timeout.
-    fn set_timeout(&self, timeout: Timeout) {
-        self.queue.lock(|queue| {
-            queue.push(timeout);
+    /// Run `f` with the queues of all cores locked.
+    ///
+    /// The locks are taken in the order of the cores, which `offline_secondary()` follows as well.
+    /// A timeout moving to the boot core is therefore seen either in its old queue or in its new
+    /// one.
+    fn with_all_queues<R>(&self, f: impl FnOnce(&mut [&mut OrderedTimeoutQueue]) -> R) -> R {
+        fn lock_from(
+            queues: &PerCpu<IRQSafeLock<OrderedTimeoutQueue>>,
+            locked: &mut Vec<*mut OrderedTimeoutQueue>,
+            f: &mut dyn FnMut(&mut [&mut OrderedTimeoutQueue]),
+        ) {
+            match queues.iter().nth(locked.len()) {
+                None => {
+                    // Each pointer comes from a lock that is still held further up the stack.
+                    let mut queues: Vec<&mut OrderedTimeoutQueue> =
+                        locked.iter().map(|&queue| unsafe { &mut *queue }).collect();
+                    f(&mut queues)
+                }
+                Some(queue) => queue.lock(|queue| {
+                    locked.push(queue);
+                    lock_from(queues, locked, f);
+                }),
+            }
+        }
+
+        let mut f = Some(f);
+        let mut result = None;
+        lock_from(
+            &self.queues,
+            &mut Vec::with_capacity(NR_CPUS),
+            &mut |queues| result = f.take().map(|f| f(queues)),
+        );
 
-            arch_time::set_timeout_irq(queue.peek_next_due_time().unwrap());
-        });
+        result.unwrap()
     }
 
-    /// Set a one-shot timeout.
-    pub fn set_timeout_once(&self, delay: Duration, callback: TimeoutCallback) {
+    /// Set a timeout on `core`, or on the executing core if `None`.
+    fn set_timeout(
+        &self,
+        core: Option<usize>,
+        delay: Duration,
+        period: Option<Duration>,
+        callback: TimeoutCallback,
+    ) -> TimeoutHandle {
+        let id = NEXT_TIMEOUT_ID.fetch_add(1, Ordering::Relaxed);
         let timeout = Timeout {
             due_time: self.uptime() + delay,
-            period: None,
-            callback,
+            period,
+            pinned: core.is_some(),
+            callback: Some(callback),
         };
 
-        self.set_timeout(timeout);
+        let push = || {
+            let core = core.unwrap_or_else(core_id);
+
+            self.queues[core].lock(|queue| queue.push(id, timeout));
+            self.rearm_on(core);
+        };
+
+        // The thread must not move to another core before that core's timer is programmed. With
+        // IRQs masked it cannot, and the scheduler, which arms the tick that way, must not have a
+        // held back switch carried out by `preempt_enable()`.
+        if is_local_irq_masked() {
+            push();
+        } else {
+            preempt::without_preemption(push);
+        }
+
+        TimeoutHandle { id }
     }
 
-    /// Set a periodic timeout.
-    pub fn set_timeout_periodic(&self, delay: Duration, callback: TimeoutCallback) {
-        let timeout = Timeout {
-            due_time: self.uptime() + delay,
-            period: Some(delay),
-            callback,
+    /// Program the executing core's timer for the earliest timeout in its queue.
+    fn rearm(&self) {
+        self.queues[core_id::<usize>()].lock(|queue| {
+            if let Some(due_time) = queue.peek_next_due_time() {
+                arch_time::set_timeout_irq(due_time);
+            }
+        });
+    }
+
+    /// Have `core` program its timer for the earliest timeout in its queue.
+    ///
+    /// An offline core does so once it comes back.
+    fn rearm_on(&self, core: usize) {
+        if core == core_id::<usize>() {
+            self.rearm();
+        } else {
+            smp::call_on(core, || time_manager().rearm(), false);
+        }
+    }
+
+    /// Set a one-shot timeout on the executing core.
+    ///
+    /// Moves to the boot core if the executing core goes offline.
+    pub fn set_timeout_once(&self, delay: Duration, callback: TimeoutCallback) -> TimeoutHandle {
+        self.set_timeout(None, delay, None, callback)
+    }
+
+    /// Set a periodic timeout on the executing core.
+    ///
+    /// Moves to the boot core if the executing core goes offline.
+    pub fn set_timeout_periodic(
+        &self,
+        delay: Duration,
+        callback: TimeoutCallback,
+    ) -> TimeoutHandle {
+        self.set_timeout(None, delay, Some(delay), callback)
+    }
+
+    /// Set a one-shot timeout on `core`.
+    ///
+    /// It always fires on `core`, waiting for it to come back if it is offline.
+    pub fn set_timeout_once_on(
+        &self,
+        core: usize,
+        delay: Duration,
+        callback: TimeoutCallback,
+    ) -> TimeoutHandle {
+        self.set_timeout(Some(core), delay, None, callback)
+    }
+
+    /// Set a periodic timeout on `core`.
+    ///
+    /// It always fires on `core`, waiting for it to come back if it is offline.
+    pub fn set_timeout_periodic_on(
+        &self,
+        core: usize,
+        delay: Duration,
+        callback: TimeoutCallback,
+    ) -> TimeoutHandle {
+        self.set_timeout(Some(core), delay, Some(delay), callback)
+    }
+
+    /// Return a future that completes once `duration` has passed.
+    ///
+    /// The counterpart of `thread::sleep_for()` for tasks run by the executor.
+    pub fn sleep(&self, duration: Duration) -> Sleep {
+        Sleep {
+            deadline: self.uptime() + duration,
+            state: None,
+            timeout: None,
+        }
+    }
+}
+
+impl TimeoutHandle {
+    /// Cancel the timeout. Returns whether it was still pending.
+    ///
+    /// A periodic timeout whose callback is running right now completes that run.
+    pub fn cancel(&self) -> bool {
+        time_manager()
+            .with_all_queues(|queues| queues.iter_mut().any(|queue| queue.cancel(self.id)))
+    }
+
+    /// Move the timeout to the uptime `new_due`. Returns whether it was still pending.
+    ///
+    /// A periodic timeout keeps its period, counted from `new_due` on.
+    pub fn modify(&self, new_due: Duration) -> bool {
+        let manager = time_manager();
+
+        preempt::without_preemption(|| {
+            let core = manager.with_all_queues(|queues| {
+                queues
+                    .iter_mut()
+                    .position(|queue| queue.modify(self.id, new_due))
+            });
+            core.map(|core| manager.rearm_on(core)).is_some()
+        })
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let now = time_manager().uptime();
+        if now >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        let state = match &self.state {
+            Some(state) => state.clone(),
+            None => {
+                let state = Arc::new(SleepState {
+                    expired: AtomicBool::new(false),
+                    waker: IRQSafeLock::new(SpinLock::new(None)),
+                });
+
+                let timer_state = state.clone();
+                self.timeout = Some(time_manager().set_timeout_once(
+                    self.deadline - now,
+                    Box::new(move |_| {
+                        timer_state.expired.store(true, Ordering::Release);
+                        let waker = timer_state
+                            .waker
+                            .lock(|spin_lock| spin_lock.lock(|waker| waker.take()));
+                        if let Some(waker) = waker {
+                            waker.wake();
+                        }
+                    }),
+                ));
+
+                self.state = Some(state.clone());
+                state
+            }
         };
 
-        self.set_timeout(timeout);
+        // Registered before looking at `expired`, so that a timeout firing in between is not
+        // missed.
+        state.waker.lock(|spin_lock| {
+            spin_lock.lock(|waker| match waker {
+                Some(w) if w.will_wake(cx.waker()) => (),
+                _ => *waker = Some(cx.waker().clone()),
+            })
+        });
+
+        if state.expired.load(Ordering::Acquire) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(timeout) = self.timeout {
+            timeout.cancel();
+        }
     }
 }
 
@@ -180,46 +540,34 @@ impl driver::interface::DeviceDriver for TimeManager {
 impl exception::asynchronous::interface::IRQHandler for TimeManager {
     fn handle(&self, e: &mut ExceptionContext) -> Result<(), &'static str> {
         arch_time::conclude_timeout_irq();
+        let queue = &self.queues[core_id::<usize>()];
 
-        let maybe_timeout: Option<Timeout> = self.queue.lock(|queue| {
-            let next_due_time = queue.peek_next_due_time()?;
-            if next_due_time > self.uptime() {
-                return None;
-            }
-            let mut timeout = queue.pop().unwrap();
+        let maybe_timeout = queue.lock(|queue| {
+            let timeout = queue.pop_due(self.uptime());
 
-            // Refresh as early as possible to prevent drift.
-            if timeout.is_periodic() {
-                timeout.refresh();
+            // The timeout the timer was programmed for might have been cancelled or moved. Either
+            // way, the timer stays armed for whatever is next.
+            if timeout.is_none() {
+                if let Some(due_time) = queue.peek_next_due_time() {
+                    arch_time::set_timeout_irq(due_time);
+                }
             }
 
-            Some(timeout)
+            timeout
         });
 
-        let timeout = match maybe_timeout {
-            None => {
-                warn!("Spurious timeout IRQ");
-                return Ok(());
-            }
+        let (id, callback) = match maybe_timeout {
+            None => return Ok(()),
             Some(t) => t,
         };
 
         // Important: Call the callback while not holding any lock, because the callback might
         // attempt to modify data that is protected by a lock (in particular, the timeout queue
         // itself).
-        (timeout.callback)(e);
-
-        self.queue.lock(|queue| {
-            if timeout.is_periodic() {
-                // There might be some overhead involved in the periodic path, because the timeout
-                // item is first popped from the underlying Vec and then pushed back again. It could
-                // be faster to keep the item in the queue and find a way to work with a reference
-                // to it.
-                //
-                // We are not going this route on purpose, though. It allows to keep the code simple
-                // and the focus on the high-level concepts.
-                queue.push(timeout);
-            };
+        callback(e);
+
+        queue.lock(|queue| {
+            queue.put_back(id, callback);
 
             if let Some(due_time) = queue.peek_next_due_time() {
                 arch_time::set_timeout_irq(due_time);
@@ -244,3 +592,102 @@ pub fn init() -> Result<(), &'static str> {
     INIT_DONE.store(true, Ordering::Relaxed);
     Ok(())
 }
+
+/// Stop the timer of a secondary core that goes offline.
+///
+/// Its timeouts move to the boot core, except the pinned ones, which wait for it to come back.
+pub fn offline_secondary() {
+    arch_time::conclude_timeout_irq();
+
+    let core: usize = core_id();
+    let boot_core = BOOT_CORE_ID as usize;
+    let queues = &time_manager().queues;
+
+    // The boot core's queue is locked first, in the same order `TimeoutHandle` locks all queues.
+    queues[boot_core].lock(|to| queues[core].lock(|from| from.move_unpinned_to(to)));
+    time_manager().rearm_on(boot_core);
+}
+
+/// Enable the timeout IRQ on a secondary core.
+///
+/// The IRQ is private to each core, so `init()` only enabled it on the boot core. Afterwards, the
+/// core can be woken up from idle by its own timer. Must be called after `smp::mark_online()`, so
+/// that timeouts set on the core before are not missed.
+pub fn init_secondary() {
+    exception::asynchronous::irq_manager().enable(&arch_time::timeout_irq());
+    time_manager().rearm();
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    fn one_shot(due_ms: u64) -> Timeout {
+        Timeout {
+            due_time: Duration::from_millis(due_ms),
+            period: None,
+            pinned: false,
+            callback: Some(Box::new(|_| {})),
+        }
+    }
+
+    fn pop_id(queue: &mut OrderedTimeoutQueue, now_ms: u64) -> Option<u64> {
+        queue
+            .pop_due(Duration::from_millis(now_ms))
+            .map(|(id, _)| id)
+    }
+
+    /// Check that timeouts come out by due time, not by insertion order.
+    #[kernel_test]
+    fn timeouts_pop_in_due_order() {
+        let mut queue = OrderedTimeoutQueue::new();
+        queue.push(0, one_shot(30));
+        queue.push(1, one_shot(10));
+        queue.push(2, one_shot(20));
+
+        assert_eq!(pop_id(&mut queue, 5), None);
+        assert_eq!(pop_id(&mut queue, 100), Some(1));
+        assert_eq!(pop_id(&mut queue, 100), Some(2));
+        assert_eq!(pop_id(&mut queue, 100), Some(0));
+        assert_eq!(pop_id(&mut queue, 100), None);
+    }
+
+    /// Check that the entries left behind by cancel and modify are skipped.
+    #[kernel_test]
+    fn stale_entries_are_skipped() {
+        let mut queue = OrderedTimeoutQueue::new();
+        queue.push(0, one_shot(10));
+        queue.push(1, one_shot(20));
+        queue.push(2, one_shot(30));
+
+        assert!(queue.cancel(0));
+        assert!(queue.modify(1, Duration::from_millis(40)));
+        assert!(!queue.cancel(0));
+        assert!(!queue.modify(0, Duration::from_millis(50)));
+
+        assert_eq!(queue.peek_next_due_time(), Some(Duration::from_millis(30)));
+        assert_eq!(pop_id(&mut queue, 35), Some(2));
+        assert_eq!(pop_id(&mut queue, 35), None);
+        assert_eq!(pop_id(&mut queue, 40), Some(1));
+        assert_eq!(queue.peek_next_due_time(), None);
+    }
+
+    /// Check that a timeout modified over and over does not grow the heap without bounds.
+    #[kernel_test]
+    fn stale_entries_are_compacted() {
+        let mut queue = OrderedTimeoutQueue::new();
+        queue.push(0, one_shot(10));
+
+        for due_ms in 11..100 {
+            assert!(queue.modify(0, Duration::from_millis(due_ms)));
+            assert!(queue.due.len() <= 2);
+        }
+
+        assert_eq!(queue.peek_next_due_time(), Some(Duration::from_millis(99)));
+    }
+}